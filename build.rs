@@ -1,12 +1,284 @@
 use embuild::espidf::sysenv;
 
-use std::path::PathBuf;
+use std::{
+    env, fmt, fs,
+    path::{Path, PathBuf},
+};
+
+/// Base name of the external secrets file looked up in `current_dir` and its
+/// ancestors, so a single file can be shared across sibling firmware crates.
+const EXTERNAL_SECRETS_BASENAME: &str = "erik.secrets";
+
+/// Candidate extensions tried (in order) for `EXTERNAL_SECRETS_BASENAME`, and
+/// the format they're parsed as.
+const CANDIDATE_EXTENSIONS: &[(&str, SecretsFormat)] = &[
+    ("toml", SecretsFormat::Toml),
+    ("json", SecretsFormat::Json),
+    ("yaml", SecretsFormat::Yaml),
+    ("yml", SecretsFormat::Yaml),
+];
+
+/// Env var naming an explicit secrets file path, bypassing the ancestor
+/// search. Its extension must be one `SecretsFormat::from_extension` knows.
+const EXPLICIT_SECRETS_FILE_VAR: &str = "ERIK_SECRETS_FILE";
+
+/// Defaults used when the corresponding environment variable is unset.
+/// Mirrors `src/secrets.rs.example`.
+const DEFAULT_SSID: &str = "ExampleSSID";
+const DEFAULT_PASSWORD: &str = "ExamplePassword";
+
+#[derive(Clone, Copy)]
+enum SecretsFormat {
+    Toml,
+    Json,
+    Yaml,
+}
+
+impl SecretsFormat {
+    fn from_extension(ext: &str) -> Option<Self> {
+        CANDIDATE_EXTENSIONS
+            .iter()
+            .find(|(candidate, _)| ext.eq_ignore_ascii_case(candidate))
+            .map(|(_, format)| *format)
+    }
+
+    fn parse(self, contents: &str) -> Result<(String, String), String> {
+        match self {
+            SecretsFormat::Toml => parse_key_value(contents, '=', &['"', '\'']),
+            SecretsFormat::Json => parse_json(contents),
+            SecretsFormat::Yaml => parse_key_value(contents, ':', &['"', '\'']),
+        }
+    }
+}
+
+impl fmt::Display for SecretsFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SecretsFormat::Toml => "TOML",
+            SecretsFormat::Json => "JSON",
+            SecretsFormat::Yaml => "YAML",
+        })
+    }
+}
 
 fn main() {
     sysenv::output();
-    let secretfile = PathBuf::from("src/secrets.rs");
-    if !secretfile.exists() {
-        println!("cargo::warning=Using secrets.rs.example, with some default secrets {}", std::env::current_dir().unwrap().display());
-        std::fs::copy("src/secrets.rs.example", secretfile).expect("copy of secrets.rs.example to secrets.rs failed");
+    maybe_bootstrap_sysroot();
+
+    let (ssid, password) = match resolve_secrets_file() {
+        Some((path, format)) => {
+            println!("cargo::rerun-if-changed={}", path.display());
+            let contents = fs::read_to_string(&path)
+                .unwrap_or_else(|err| panic!("failed to read {}: {err}", path.display()));
+            format
+                .parse(&contents)
+                .unwrap_or_else(|err| panic!("failed to parse {} as {format}: {err}", path.display()))
+        }
+        None => (
+            env_or_default("ERIK_WIFI_SSID", DEFAULT_SSID),
+            env_or_default("ERIK_WIFI_PASSWORD", DEFAULT_PASSWORD),
+        ),
+    };
+
+    let out_dir = PathBuf::from(env::var_os("OUT_DIR").expect("OUT_DIR not set"));
+    let generated = format!(
+        "pub fn get() -> ::std::collections::HashMap<&'static str, &'static str> {{\n    \
+         ::std::collections::HashMap::from([({ssid:?}, {password:?})])\n}}\n",
+    );
+    fs::write(out_dir.join("secrets.rs"), generated).expect("failed to write generated secrets.rs");
+}
+
+/// Reads `key` from the environment, falling back to `default` when unset,
+/// and registers the variable so Cargo reruns the build script if it changes.
+fn env_or_default(key: &str, default: &str) -> String {
+    println!("cargo::rerun-if-env-changed={key}");
+    env::var(key).unwrap_or_else(|_| default.to_owned())
+}
+
+/// Finds the external secrets file to use, if any: an explicit path via
+/// `ERIK_SECRETS_FILE` takes priority (and must have a known extension),
+/// otherwise we walk upward from `current_dir` looking for
+/// `EXTERNAL_SECRETS_BASENAME` with one of `CANDIDATE_EXTENSIONS`.
+fn resolve_secrets_file() -> Option<(PathBuf, SecretsFormat)> {
+    println!("cargo::rerun-if-env-changed={EXPLICIT_SECRETS_FILE_VAR}");
+    if let Ok(explicit) = env::var(EXPLICIT_SECRETS_FILE_VAR) {
+        let path = PathBuf::from(explicit);
+        let ext = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .unwrap_or_default();
+        let format = SecretsFormat::from_extension(ext)
+            .unwrap_or_else(|| panic!("{EXPLICIT_SECRETS_FILE_VAR} has unknown extension {ext:?}"));
+        return Some((path, format));
+    }
+
+    find_ancestor_file(EXTERNAL_SECRETS_BASENAME)
+}
+
+/// Walks upward from the current directory to the filesystem root, trying
+/// `{dir}/{base}.{ext}` for each candidate extension at each level, and
+/// stopping at the first match. Directories that can't be read (permissions,
+/// removed, etc.) are skipped rather than treated as errors.
+fn find_ancestor_file(base: &str) -> Option<(PathBuf, SecretsFormat)> {
+    let mut dir = env::current_dir().ok()?;
+    loop {
+        for (ext, format) in CANDIDATE_EXTENSIONS {
+            let candidate = dir.join(format!("{base}.{ext}"));
+            if fs::metadata(&candidate).is_ok() {
+                return Some((candidate, *format));
+            }
+        }
+        if !dir.pop() {
+            return None;
+        }
+    }
+}
+
+/// Minimal `key<sep>"value"` line parser shared by the TOML and YAML paths;
+/// a real parser crate would be overkill for a build script that only ever
+/// reads `ssid` and `password`.
+fn parse_key_value(
+    contents: &str,
+    separator: char,
+    quote_chars: &[char],
+) -> Result<(String, String), String> {
+    let mut ssid = None;
+    let mut password = None;
+    for line in contents.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (key, value) = line
+            .split_once(separator)
+            .ok_or_else(|| format!("malformed line: {line:?}"))?;
+        let value = value.trim().trim_matches(quote_chars).to_owned();
+        match key.trim().trim_matches(quote_chars) {
+            "ssid" => ssid = Some(value),
+            "password" => password = Some(value),
+            other => return Err(format!("unknown key {other:?}")),
+        }
+    }
+    Ok((
+        ssid.ok_or("missing `ssid` key")?,
+        password.ok_or("missing `password` key")?,
+    ))
+}
+
+/// Minimal `{"ssid": "...", "password": "..."}` parser, deliberately not
+/// pulling in `serde_json` for a two-field build-time document.
+fn parse_json(contents: &str) -> Result<(String, String), String> {
+    let trimmed = contents.trim().trim_start_matches('{').trim_end_matches('}');
+    parse_key_value(trimmed.replace(',', "\n").trim(), ':', &['"'])
+}
+
+/// Env var / feature gate for the opt-in custom-sysroot bootstrap below.
+/// Unset (the default) leaves the normal Cargo-selected sysroot untouched.
+const BUILD_STD_ENV: &str = "ERIK_BUILD_STD";
+
+/// Builds a `core`/`alloc` sysroot for the target into an `OUT_DIR`-keyed
+/// cache directory and links against it, when `ERIK_BUILD_STD=1`. This is
+/// for no_std ESP-IDF targets that need rustflags (e.g. a custom panic
+/// strategy) baked into `core` itself, which stock Cargo can't do without
+/// `-Zbuild-std` wired up by the caller.
+fn maybe_bootstrap_sysroot() {
+    println!("cargo::rerun-if-env-changed={BUILD_STD_ENV}");
+    if env::var(BUILD_STD_ENV).as_deref() != Ok("1") {
+        return;
+    }
+    if let Err(err) = bootstrap_sysroot() {
+        panic!("custom sysroot bootstrap failed: {err}");
+    }
+}
+
+fn bootstrap_sysroot() -> Result<(), String> {
+    let target = env::var("TARGET").map_err(|_| "TARGET not set".to_string())?;
+    let rustflags = env::var("CARGO_ENCODED_RUSTFLAGS").unwrap_or_default();
+
+    let sysroot = rustc_print_sysroot()?;
+    let src_dir = sysroot.join("lib/rustlib/src/rust/library");
+    let src_dir = fs::canonicalize(&src_dir)
+        .map_err(|err| format!("rust source not found at {}: {err}", src_dir.display()))?;
+
+    // Canonicalized inputs keep the cache key stable across equivalent but
+    // differently-spelled paths, so unrelated builds don't keep re-triggering it.
+    let cache_key = fnv1a_hex(&[&target, &rustflags, &src_dir.display().to_string()]);
+    let out_dir = PathBuf::from(env::var_os("OUT_DIR").expect("OUT_DIR not set"));
+    let sysroot_out = out_dir.join("custom-sysroot").join(&cache_key);
+    let stamp = sysroot_out.join(".built");
+
+    if !stamp.exists() {
+        fs::create_dir_all(&sysroot_out).map_err(|err| err.to_string())?;
+
+        let output = std::process::Command::new("cargo")
+            .args([
+                "build",
+                "-Zbuild-std=core,alloc",
+                "--target",
+                &target,
+                "--release",
+                "--manifest-path",
+            ])
+            .arg(src_dir.join("sysroot").join("Cargo.toml"))
+            .env("RUSTFLAGS", &rustflags)
+            .env("CARGO_TARGET_DIR", &sysroot_out)
+            .output()
+            .map_err(|err| format!("failed to spawn cargo for sysroot build: {err}"))?;
+
+        if !output.status.success() {
+            return Err(format!(
+                "sysroot build exited with {}:\n{}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr)
+            ));
+        }
+
+        fs::write(&stamp, b"").map_err(|err| err.to_string())?;
+    }
+
+    println!(
+        "cargo::rustc-link-search=native={}",
+        sysroot_out.join(&target).join("release").display()
+    );
+
+    // No matching `cargo::rustc-flags` directive here: Cargo has no general
+    // directive for forwarding arbitrary rustc flags to the crate being
+    // built (`cargo::rustc-flags` itself only accepts `-l`/`-L`). That's
+    // fine in this case — `rustflags` above is read straight from
+    // `CARGO_ENCODED_RUSTFLAGS`, the same value Cargo already applies to
+    // this crate's own compilation, so the subprocess sysroot build and the
+    // main build see identical flags without anything needing to
+    // re-propagate them.
+    Ok(())
+}
+
+fn rustc_print_sysroot() -> Result<PathBuf, String> {
+    let output = std::process::Command::new("rustc")
+        .args(["--print", "sysroot"])
+        .output()
+        .map_err(|err| format!("failed to run rustc: {err}"))?;
+    if !output.status.success() {
+        return Err(format!(
+            "rustc --print sysroot exited with {}",
+            output.status
+        ));
+    }
+    let path = String::from_utf8(output.stdout)
+        .map_err(|err| err.to_string())?
+        .trim()
+        .to_owned();
+    Ok(PathBuf::from(path))
+}
+
+/// Cheap, dependency-free FNV-1a hash of the cache-key inputs; we only need
+/// stability and good-enough distribution, not cryptographic strength.
+fn fnv1a_hex(parts: &[&str]) -> String {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for part in parts {
+        for byte in part.bytes() {
+            hash ^= u64::from(byte);
+            hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+        }
     }
+    format!("{hash:016x}")
 }
@@ -1,18 +1,31 @@
 //! Next-generation firmware scaffold for the solar pool heater controller.
 
 use std::{
+    collections::HashMap,
     sync::Mutex,
     time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
-use anyhow::{anyhow, Result};
+use aes_gcm::{
+    aead::{Aead, KeyInit},
+    Aes256Gcm, Key, Nonce,
+};
+use anyhow::{anyhow, Context, Result};
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use embedded_svc::http::{client::Client as HttpClient, Method as HttpMethod};
 use esp_idf_svc::{
-    http::server::{self, EspHttpConnection, EspHttpServer, Method, Request},
+    http::{
+        client::{Configuration as HttpClientConfiguration, EspHttpConnection as HttpClientConnection},
+        server::{self, ws::EspHttpWsConnection, EspHttpConnection, EspHttpServer, Method, Request},
+    },
     io::{Read, Write},
     log::EspLogger,
     nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault},
+    ota::EspOta,
     sys::{self, EspError, ESP_ERR_NVS_NOT_FOUND},
+    ws::FrameType,
 };
+use hmac::{Hmac, Mac};
 use include_dir::{include_dir, Dir};
 use log::{error, info, warn};
 use once_cell::sync::Lazy;
@@ -20,6 +33,7 @@ use pbkdf2::pbkdf2_hmac;
 use serde::{Deserialize, Serialize};
 use serde::de::DeserializeOwned;
 use serde_json::json;
+use sha1::Sha1;
 use sha2::Sha256;
 use time::OffsetDateTime;
 use rand::{rngs::OsRng, RngCore};
@@ -30,6 +44,32 @@ fn hex_encode(bytes: &[u8]) -> String {
     bytes.iter().map(|b| format!("{:02x}", b)).collect()
 }
 
+fn hex_encode_upper(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02X}", b)).collect()
+}
+
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// RFC 4648 base32 encoding without padding, used for TOTP secrets in
+/// `otpauth://` provisioning URIs.
+fn base32_encode(bytes: &[u8]) -> String {
+    let mut output = String::with_capacity((bytes.len() * 8).div_ceil(5));
+    let mut buffer: u32 = 0;
+    let mut bits = 0u32;
+    for &byte in bytes {
+        buffer = (buffer << 8) | byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            output.push(BASE32_ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        output.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+    output
+}
+
 fn hex_decode(s: &str) -> Result<Vec<u8>, std::num::ParseIntError> {
     (0..s.len())
         .step_by(2)
@@ -52,9 +92,70 @@ const NVS_KEY_USERNAME: &str = "user";
 const NVS_KEY_PASSWORD_HASH: &str = "pwd_hash";
 const NVS_KEY_SALT: &str = "pwd_salt";
 const NVS_KEY_PROVISIONED: &str = "prov";
+const NVS_KEY_TOTP_SECRET: &str = "totp_secret";
+const NVS_KEY_TOTP_ENABLED: &str = "totp_en";
 const MAX_NVS_STR_LEN: usize = 128;
+/// RFC 6238 parameters for the optional TOTP second factor.
+const TOTP_SECRET_LEN: usize = 20;
+const TOTP_STEP_SECONDS: u64 = 30;
+const TOTP_DIGITS: u32 = 6;
+/// Accepted time-step drift either side of "now" when validating a code.
+const TOTP_WINDOW_STEPS: i64 = 1;
+const TOTP_ISSUER: &str = "erik";
 const SESSION_IDLE_TIMEOUT: Duration = Duration::from_secs(15 * 60);
+const MAX_SESSIONS_PER_CREDENTIAL: usize = 8;
 const MAX_FIRMWARE_SIZE: usize = 2 * 1024 * 1024;
+const FIRMWARE_CHUNK_SIZE: usize = 4096;
+const FIRMWARE_SIGNATURE_HEADER: &str = "X-Firmware-Signature";
+const FIRMWARE_SIGNATURE_LEN: usize = 64;
+const NVS_KEY_FW_PUBKEY: &str = "fw_pubkey";
+/// Compiled-in Ed25519 verifying key trusted for firmware signing. Replace
+/// with the deployment's real public key before shipping; a matching key in
+/// NVS under `fw_pubkey` (hex-encoded) can rotate it without reflashing, via
+/// `handle_firmware_signer_key_rotate`.
+const TRUSTED_FW_PUBKEY: [u8; 32] = [
+    0x3b, 0x6a, 0x27, 0xbc, 0xce, 0xb6, 0xa4, 0x2d, 0x62, 0xa3, 0xa8, 0xd0, 0x2a, 0x6f, 0x0d, 0x73,
+    0x65, 0x32, 0x15, 0x77, 0x1d, 0xe2, 0x43, 0xa6, 0x3a, 0xc0, 0x48, 0xa1, 0x8b, 0x59, 0xda, 0x29,
+];
+/// How often `main`'s background loop pushes a heartbeat frame to connected
+/// `/api/ws` subscribers, independent of any probe/relay change events.
+const WS_HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
+/// Minimum change in the controlling probe's reading, in Fahrenheit, before
+/// `thermostat_tick` bothers pushing a `probe` WS event. Keeps sensor noise
+/// from flooding subscribers every `THERMOSTAT_POLL_INTERVAL`.
+const PROBE_BROADCAST_EPSILON_F: f32 = 0.05;
+/// How often the background thread in `main` calls `thermostat_tick`.
+const THERMOSTAT_POLL_INTERVAL: Duration = Duration::from_secs(30);
+/// Base URL for the Have I Been Pwned k-anonymity range endpoint consulted
+/// by `check_breached_password`.
+const HIBP_RANGE_URL: &str = "https://api.pwnedpasswords.com/range/";
+const HIBP_TIMEOUT: Duration = Duration::from_secs(5);
+/// Consecutive failures from one source allowed before `LoginThrottle` starts
+/// imposing backoff on `/api/login` and `/api/provisioning`.
+const LOGIN_ATTEMPT_THRESHOLD: u32 = 5;
+/// Failures older than this are treated as a fresh run rather than adding to
+/// an existing streak.
+const LOGIN_ATTEMPT_WINDOW: Duration = Duration::from_secs(15 * 60);
+/// Backoff duration doubles with each failure past `LOGIN_ATTEMPT_THRESHOLD`,
+/// starting here and saturating at `LOGIN_BACKOFF_CAP`.
+const LOGIN_BACKOFF_BASE: Duration = Duration::from_secs(1);
+const LOGIN_BACKOFF_CAP: Duration = Duration::from_secs(60);
+/// Bounds `LoginThrottle`'s map so a flood of distinct sources can't grow it
+/// without limit; the oldest tracked source is evicted to make room.
+const MAX_LOGIN_THROTTLE_SOURCES: usize = 256;
+/// Length of the AES-256-GCM app key derived from the admin password, used
+/// to encrypt secrets at rest (currently just the TOTP seed) that don't
+/// need to be readable before someone has authenticated.
+const APP_KEY_LEN: usize = 32;
+const APP_KEY_SALT_LEN: usize = 16;
+const APP_KEY_PBKDF2_ITERATIONS: u32 = 100_000;
+const NVS_KEY_APP_KEY_SALT: &str = "app_key_salt";
+const NVS_KEY_APP_KEY_VERIFY: &str = "app_key_verify";
+/// Known plaintext encrypted under the app key and stored alongside its
+/// salt; decrypting it successfully on unlock proves the re-derived key
+/// (and therefore the supplied password) is correct.
+const APP_KEY_VERIFY_PLAINTEXT: &[u8] = b"erik-app-key-v1";
+const AES_GCM_NONCE_LEN: usize = 12;
 const SESSION_EXPIRED_HEADER: &str = "X-Session-Expired";
 const WWW_AUTH_INVALID: &str = "Bearer error=\"invalid_token\"";
 const WWW_AUTH_EXPIRED: &str =
@@ -75,8 +176,16 @@ fn main() -> Result<()> {
     let _server = start_http_server()?;
     info!("HTTP server started; serving embedded web UI");
 
+    std::thread::spawn(|| loop {
+        thermostat_tick();
+        std::thread::sleep(THERMOSTAT_POLL_INTERVAL);
+    });
+
     loop {
-        std::thread::sleep(Duration::from_secs(60));
+        std::thread::sleep(WS_HEARTBEAT_INTERVAL);
+        let mut state = APP_STATE.lock().unwrap();
+        expire_stale_ws_sessions(&mut state);
+        broadcast_event(&mut state, &json!({"type": "heartbeat", "uptimeSeconds": START_TIME.elapsed().as_secs()}));
     }
 }
 
@@ -135,6 +244,12 @@ fn start_http_server() -> Result<EspHttpServer<'static>> {
             Err(err) => respond_error(req, 400, &format!("Invalid relay payload: {err}")),
         }
     })?;
+    server.fn_handler("/api/relay/auto", Method::Post, |req| {
+        if let Err(failure) = authorize(&req) {
+            return respond_unauthorized(req, failure);
+        }
+        handle_relay_auto(req)
+    })?;
     server.fn_handler("/api/defaults", Method::Get, |req| {
         if let Err(failure) = authorize(&req) {
             return respond_unauthorized(req, failure);
@@ -202,6 +317,65 @@ fn start_http_server() -> Result<EspHttpServer<'static>> {
         }
         handle_firmware_upload(req)
     })?;
+    server.fn_handler("/api/admin/firmware/confirm", Method::Post, |req| {
+        if let Err(failure) = authorize(&req) {
+            return respond_unauthorized(req, failure);
+        }
+        handle_firmware_confirm(req)
+    })?;
+    server.fn_handler("/api/admin/firmware/signer-key", Method::Post, |mut req| {
+        if let Err(failure) = authorize(&req) {
+            return respond_unauthorized(req, failure);
+        }
+        match parse_json::<FirmwareSignerKeyRequest>(&mut req) {
+            Ok(body) => handle_firmware_signer_key_rotate(req, body),
+            Err(err) => respond_error(req, 400, &format!("Invalid signer-key payload: {err}")),
+        }
+    })?;
+    server.fn_handler("/api/admin/totp/enroll", Method::Post, |req| {
+        if let Err(failure) = authorize(&req) {
+            return respond_unauthorized(req, failure);
+        }
+        handle_totp_enroll(req)
+    })?;
+    server.fn_handler("/api/admin/totp/confirm", Method::Post, |mut req| {
+        if let Err(failure) = authorize(&req) {
+            return respond_unauthorized(req, failure);
+        }
+        match parse_json::<TotpConfirmRequest>(&mut req) {
+            Ok(body) => handle_totp_confirm(req, body),
+            Err(err) => respond_error(req, 400, &format!("Invalid TOTP payload: {err}")),
+        }
+    })?;
+    server.fn_handler("/api/admin/totp/disable", Method::Post, |mut req| {
+        if let Err(failure) = authorize(&req) {
+            return respond_unauthorized(req, failure);
+        }
+        match parse_json::<TotpDisableRequest>(&mut req) {
+            Ok(body) => handle_totp_disable(req, body),
+            Err(err) => respond_error(req, 400, &format!("Invalid TOTP payload: {err}")),
+        }
+    })?;
+    server.fn_handler("/api/admin/sessions", Method::Get, |req| {
+        if let Err(failure) = authorize(&req) {
+            return respond_unauthorized(req, failure);
+        }
+        handle_list_sessions(req)
+    })?;
+    server.fn_handler("/api/admin/sessions", Method::Delete, |req| {
+        if let Err(failure) = authorize(&req) {
+            return respond_unauthorized(req, failure);
+        }
+        handle_revoke_other_sessions(req)
+    })?;
+    server.fn_handler("/api/admin/sessions/*", Method::Delete, |req| {
+        if let Err(failure) = authorize(&req) {
+            return respond_unauthorized(req, failure);
+        }
+        handle_revoke_session(req)
+    })?;
+
+    server.ws_handler("/api/ws", handle_ws)?;
 
     Ok(server)
 }
@@ -240,8 +414,14 @@ fn handle_post_provisioning(
     mut req: Request<&mut EspHttpConnection>,
     body: ProvisioningRequest,
 ) -> Result<(), EspError> {
+    let source = client_source_key(&req);
+    let now = SystemTime::now();
     let mut state = APP_STATE.lock().unwrap();
+    if let Some(remaining) = state.login_throttle.check(&source, now) {
+        return respond_throttled(req, remaining);
+    }
     if state.provisioned {
+        state.login_throttle.record_failure(&source, now);
         return respond_error(req, 409, "Already provisioned");
     }
 
@@ -254,20 +434,50 @@ fn handle_post_provisioning(
         .to_owned();
 
     if body.password.trim().len() < 8 {
+        state.login_throttle.record_failure(&source, now);
         return respond_error(req, 400, "Password must be at least 8 characters");
     }
 
+    // The HIBP lookup is a blocking network round-trip, so release the lock
+    // for its duration rather than stalling every other handler behind it.
+    let check_breached = state.defaults.check_breached_passwords;
+    drop(state);
+    if check_breached {
+        if let Some(count) = check_breached_password(&body.password) {
+            let mut state = APP_STATE.lock().unwrap();
+            state.login_throttle.record_failure(&source, now);
+            return respond_error(
+                req,
+                400,
+                &format!("Password has appeared in {count} known data breaches"),
+            );
+        }
+    }
+    let mut state = APP_STATE.lock().unwrap();
+    if state.provisioned {
+        state.login_throttle.record_failure(&source, now);
+        return respond_error(req, 409, "Already provisioned");
+    }
+
     let previous_credentials = state.credentials.clone();
     let previous_provisioned = state.provisioned;
+    let previous_app_key = state.app_key;
     state.credentials = Credentials::with_password(&username, &body.password);
     state.provisioned = true;
-    if let Err(err) = persist_credentials_state(&state.credentials, state.provisioned) {
+    let persisted = rotate_app_key_locked(&body.password).and_then(|app_key| {
+        state.app_key = Some(app_key);
+        persist_credentials_state(&state.credentials, state.provisioned, &app_key)
+    });
+    if let Err(err) = persisted {
         error!("Failed to persist credentials: {err}");
         state.credentials = previous_credentials;
         state.provisioned = previous_provisioned;
+        state.app_key = previous_app_key;
+        state.login_throttle.record_failure(&source, now);
         return respond_error(req, 500, "Failed to persist credentials");
     }
-    let token = state.credentials.issue_token();
+    state.login_throttle.record_success(&source);
+    let token = state.credentials.issue_token(client_label(&req));
     let response = json!({
         "provisioned": true,
         "token": token,
@@ -281,17 +491,59 @@ fn handle_login(
     mut req: Request<&mut EspHttpConnection>,
     body: LoginRequest,
 ) -> Result<(), EspError> {
+    let source = client_source_key(&req);
+    let now = SystemTime::now();
     let mut state = APP_STATE.lock().unwrap();
+    if let Some(remaining) = state.login_throttle.check(&source, now) {
+        return respond_throttled(req, remaining);
+    }
+    if let Some(remaining) = state.account_lockout.check(&body.username, now) {
+        return respond_throttled(req, remaining);
+    }
     if !state.provisioned {
         return respond_error(req, 423, "Provisioning required");
     }
     if body.username != state.credentials.username {
-        return respond_error(req, 401, "Invalid credentials");
+        state.login_throttle.record_failure(&source, now);
+        state.account_lockout.record_failure(&body.username, now);
+        let locked_for = state.account_lockout.check(&body.username, now);
+        return respond_json(req, 401, &login_failure_body("Invalid credentials", locked_for));
     }
     if !state.credentials.verify_password(&body.password) {
-        return respond_error(req, 401, "Invalid credentials");
+        state.login_throttle.record_failure(&source, now);
+        state.account_lockout.record_failure(&body.username, now);
+        let locked_for = state.account_lockout.check(&body.username, now);
+        return respond_json(req, 401, &login_failure_body("Invalid credentials", locked_for));
+    }
+    // Re-derive the encryption-at-rest app key now that the password is
+    // known, and use it to decrypt any stored TOTP secret into memory.
+    match unlock_app_key_locked(&body.password) {
+        Ok(app_key) => {
+            unlock_totp_secret(&mut state.credentials, &app_key);
+            state.app_key = Some(app_key);
+        }
+        Err(err) => warn!("Failed to unlock app key: {err}"),
+    }
+    if state.credentials.totp_enabled {
+        let valid = body
+            .totp
+            .as_deref()
+            .is_some_and(|code| state.credentials.verify_totp(code));
+        if !valid {
+            state.login_throttle.record_failure(&source, now);
+            state.account_lockout.record_failure(&body.username, now);
+            let locked_for = state.account_lockout.check(&body.username, now);
+            // Distinct from the "Invalid credentials" case above: the
+            // password matched, so the client can prompt for just the
+            // authenticator code instead of re-asking for both.
+            let mut failure = login_failure_body("Invalid or missing authenticator code", locked_for);
+            failure["totpRequired"] = json!(true);
+            return respond_json(req, 401, &failure);
+        }
     }
-    let token = state.credentials.issue_token();
+    state.login_throttle.record_success(&source);
+    state.account_lockout.record_success(&body.username);
+    let token = state.credentials.issue_token(client_label(&req));
     respond_json(
         req,
         200,
@@ -306,23 +558,111 @@ fn handle_logout(mut req: Request<&mut EspHttpConnection>) -> Result<(), EspErro
     if let Err(failure) = authorize(&req) {
         return respond_unauthorized(req, failure);
     }
+    let token = req
+        .header("Authorization")
+        .and_then(extract_bearer_token)
+        .map(str::to_owned);
     let mut state = APP_STATE.lock().unwrap();
-    state.credentials.invalidate_token();
+    if let Some(token) = token {
+        state.credentials.remove_session(&token);
+    }
     respond_empty(req, 204)
 }
 
-fn handle_status(req: Request<&mut EspHttpConnection>) -> Result<(), EspError> {
+/// Derives a coarse client label from the `User-Agent` header, shown back to
+/// the operator in the session list so they can tell "phone" from "laptop".
+fn client_label(req: &Request<&mut EspHttpConnection>) -> String {
+    req.header("User-Agent")
+        .map(str::to_owned)
+        .unwrap_or_else(|| "unknown client".into())
+}
+
+/// Identifies the source of a login/provisioning attempt for
+/// `LoginThrottle`. Prefers the first hop of `X-Forwarded-For` when the
+/// device sits behind a reverse proxy; this abstraction doesn't otherwise
+/// expose a peer address, so direct requests (the common case on the
+/// controller's own AP) all share one bucket, which still throttles a
+/// single attacker, just not per-client.
+fn client_source_key(req: &Request<&mut EspHttpConnection>) -> String {
+    req.header("X-Forwarded-For")
+        .and_then(|value| value.split(',').next())
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .map(str::to_owned)
+        .unwrap_or_else(|| "direct".into())
+}
+
+#[derive(Serialize)]
+struct SessionSummary {
+    id: String,
+    #[serde(rename = "createdAt")]
+    created_at: String,
+    #[serde(rename = "lastSeen")]
+    last_seen: String,
+    label: String,
+    current: bool,
+}
+
+fn handle_list_sessions(req: Request<&mut EspHttpConnection>) -> Result<(), EspError> {
+    let current_token = req
+        .header("Authorization")
+        .and_then(extract_bearer_token)
+        .map(str::to_owned);
     let state = APP_STATE.lock().unwrap();
-    let response = StatusResponse {
-        wifi: state.wifi.clone(),
-        relay: state.relay.clone(),
-        probes: state.probes.clone(),
-        uptime_seconds: START_TIME.elapsed().as_secs(),
-        firmware: state.firmware.clone(),
+    let sessions: Vec<SessionSummary> = state
+        .credentials
+        .sessions
+        .iter()
+        .map(|(token, session)| SessionSummary {
+            id: session.id.clone(),
+            created_at: format_system_time(session.created_at),
+            last_seen: format_system_time(session.last_seen),
+            label: session.label.clone(),
+            current: current_token.as_deref() == Some(token.as_str()),
+        })
+        .collect();
+    respond_json(req, 200, &json!({ "sessions": sessions }))
+}
+
+fn handle_revoke_session(req: Request<&mut EspHttpConnection>) -> Result<(), EspError> {
+    let Some(id) = req
+        .uri()
+        .rsplit('/')
+        .next()
+        .filter(|segment| !segment.is_empty())
+    else {
+        return respond_error(req, 400, "Missing session id in path");
     };
+    let id = id.to_owned();
+    let mut state = APP_STATE.lock().unwrap();
+    state.credentials.remove_session_by_id(&id);
+    respond_empty(req, 204)
+}
+
+/// Revokes every session except the one making this request, so an
+/// operator can kick stale logins from other devices without logging
+/// themselves out.
+fn handle_revoke_other_sessions(req: Request<&mut EspHttpConnection>) -> Result<(), EspError> {
+    let Some(current_token) = req
+        .header("Authorization")
+        .and_then(extract_bearer_token)
+        .map(str::to_owned)
+    else {
+        return respond_error(req, 400, "Missing Authorization header");
+    };
+    let mut state = APP_STATE.lock().unwrap();
+    state.credentials.remove_other_sessions(&current_token);
+    respond_empty(req, 204)
+}
+
+fn handle_status(req: Request<&mut EspHttpConnection>) -> Result<(), EspError> {
+    let state = APP_STATE.lock().unwrap();
+    let response = status_snapshot(&state);
     respond_json(req, 200, &response)
 }
 
+/// Manual override: takes the relay out of `thermostat_tick`'s control
+/// until `/api/relay/auto` hands it back.
 fn handle_relay(
     mut req: Request<&mut EspHttpConnection>,
     body: RelayRequest,
@@ -331,13 +671,210 @@ fn handle_relay(
     match body.state.as_str() {
         "on" | "off" => {
             state.relay.state = body.state;
+            state.relay.mode = "manual".into();
+            state.relay.reason = Some("manual override".into());
             state.relay.last_change = Some(now_rfc3339());
+            state.relay_last_change_at = Some(SystemTime::now());
+            broadcast_relay_event(&mut state);
             respond_json(req, 200, &state.relay)
         }
         _ => respond_error(req, 400, "Relay state must be 'on' or 'off'"),
     }
 }
 
+/// Hands control of the relay back to `thermostat_tick` without otherwise
+/// changing its current on/off state.
+fn handle_relay_auto(req: Request<&mut EspHttpConnection>) -> Result<(), EspError> {
+    let mut state = APP_STATE.lock().unwrap();
+    state.relay.mode = "auto".into();
+    state.relay.reason = Some("returned to automatic control".into());
+    broadcast_relay_event(&mut state);
+    respond_json(req, 200, &state.relay)
+}
+
+fn broadcast_relay_event(state: &mut AppState) {
+    let event = json!({
+        "type": "relay",
+        "state": state.relay.state,
+        "mode": state.relay.mode,
+        "reason": state.relay.reason,
+        "lastChange": state.relay.last_change,
+    });
+    broadcast_event(state, &event);
+}
+
+/// Pushes the controlling probe's current reading to `/api/ws` subscribers;
+/// called from `thermostat_tick` only when it has moved by more than
+/// `PROBE_BROADCAST_EPSILON_F` since the last broadcast.
+fn broadcast_probe_event(state: &mut AppState, fahrenheit: f32) {
+    broadcast_event(state, &json!({"type": "probe", "fahrenheit": fahrenheit}));
+}
+
+/// Temperature used to drive the thermostat loop: the first enabled probe's
+/// latest reading. A future multi-probe setup could average or let the
+/// operator designate one explicitly; for now there's exactly one controlling
+/// probe slot.
+fn controlling_probe_temperature(probes: &[ProbeInfo]) -> Option<f32> {
+    probes
+        .iter()
+        .find(|probe| probe.enabled)
+        .and_then(|probe| probe.fahrenheit)
+}
+
+/// Runs one evaluation of the automatic thermostat control loop: if the
+/// relay is in "auto" mode and the minimum dwell time has elapsed since its
+/// last transition, compares the controlling probe's temperature against
+/// `min_on_temp +/- hysteresis/2` and flips the relay accordingly.
+fn thermostat_tick() {
+    let mut state = APP_STATE.lock().unwrap();
+    if state.relay.mode != "auto" {
+        return;
+    }
+
+    let Some(temperature) = controlling_probe_temperature(&state.probes) else {
+        return;
+    };
+
+    let should_broadcast_probe = state
+        .last_broadcast_probe_temperature
+        .is_none_or(|last| (temperature - last).abs() > PROBE_BROADCAST_EPSILON_F);
+    if should_broadcast_probe {
+        state.last_broadcast_probe_temperature = Some(temperature);
+        broadcast_probe_event(&mut state, temperature);
+    }
+
+    let min_dwell = Duration::from_secs(state.defaults.min_dwell_seconds as u64);
+    if let Some(last_change) = state.relay_last_change_at {
+        if SystemTime::now()
+            .duration_since(last_change)
+            .is_ok_and(|elapsed| elapsed < min_dwell)
+        {
+            return;
+        }
+    }
+
+    let half_hysteresis = state.defaults.hysteresis as f32 / 2.0;
+    let min_on_temp = state.defaults.min_on_temp as f32;
+    let on_threshold = min_on_temp + half_hysteresis;
+    let off_threshold = min_on_temp - half_hysteresis;
+
+    let (desired_state, reason) = if temperature >= on_threshold && state.relay.state != "on" {
+        (
+            Some("on"),
+            format!("probe at {temperature:.1}F rose above {on_threshold:.1}F"),
+        )
+    } else if temperature <= off_threshold && state.relay.state != "off" {
+        (
+            Some("off"),
+            format!("probe at {temperature:.1}F fell below {off_threshold:.1}F"),
+        )
+    } else {
+        (None, String::new())
+    };
+
+    if let Some(desired_state) = desired_state {
+        let now = SystemTime::now();
+        state.relay.state = desired_state.into();
+        state.relay.reason = Some(reason);
+        state.relay.last_change = Some(format_system_time(now));
+        state.relay_last_change_at = Some(now);
+        broadcast_relay_event(&mut state);
+    }
+}
+
+/// Pulls the bearer token out of `/api/ws`'s `?token=` query parameter, or
+/// failing that the `Sec-WebSocket-Protocol` header (some WebSocket clients
+/// can't set arbitrary query strings or headers, but can set a subprotocol).
+fn extract_ws_token(ws: &EspHttpWsConnection) -> Option<String> {
+    if let Some(query) = ws.uri().split_once('?').map(|(_, query)| query) {
+        for pair in query.split('&') {
+            if let Some(("token", value)) = pair.split_once('=') {
+                return Some(value.to_owned());
+            }
+        }
+    }
+    ws.header("Sec-WebSocket-Protocol").map(str::to_owned)
+}
+
+/// Handler for `/api/ws`. esp-idf-svc calls this once for the connection's
+/// opening handshake, once per inbound frame, and once on close; we only
+/// care about the opening handshake (to authorize and register the
+/// subscriber) since telemetry only ever flows server -> client.
+fn handle_ws(ws: &mut EspHttpWsConnection) -> Result<(), EspError> {
+    if ws.is_new() {
+        let Some(token) = extract_ws_token(ws) else {
+            warn!("Rejecting /api/ws connection with missing or invalid token");
+            ws.close()?;
+            return Ok(());
+        };
+        let authorized = APP_STATE
+            .lock()
+            .unwrap()
+            .credentials
+            .validate_token(&token, SystemTime::now())
+            == TokenValidation::Authorized;
+
+        if !authorized {
+            warn!("Rejecting /api/ws connection with missing or invalid token");
+            ws.close()?;
+            return Ok(());
+        }
+
+        let sender = ws.create_detached_sender()?;
+        let mut state = APP_STATE.lock().unwrap();
+        state.ws_clients.push(WsClient { sender, token });
+        let snapshot = status_snapshot(&state);
+        broadcast_event(&mut state, &json!({"type": "status", "status": snapshot}));
+    }
+    Ok(())
+}
+
+/// Serializes `payload` once and fans it out to every connected `/api/ws`
+/// subscriber, dropping any whose send fails (the connection is presumed
+/// gone; esp-idf-svc will separately invoke the handler's close callback).
+fn broadcast_event(state: &mut AppState, payload: &serde_json::Value) {
+    let Ok(bytes) = serde_json::to_vec(payload) else {
+        return;
+    };
+    state
+        .ws_clients
+        .retain_mut(|client| client.sender.send(FrameType::Text(false), &bytes).is_ok());
+}
+
+/// Pushes a `sessionExpired` event to, then drops, any `/api/ws` subscriber
+/// whose session has gone idle since it connected -- so the UI can redirect
+/// to login immediately instead of waiting on a REST call to fail with
+/// `SESSION_EXPIRED_HEADER`. Run from the heartbeat loop in `main`.
+fn expire_stale_ws_sessions(state: &mut AppState) {
+    let now = SystemTime::now();
+    let Ok(event) = serde_json::to_vec(&json!({"type": "sessionExpired"})) else {
+        return;
+    };
+    let AppState {
+        credentials,
+        ws_clients,
+        ..
+    } = state;
+    ws_clients.retain_mut(|client| {
+        if credentials.session_expired(&client.token, now) {
+            let _ = client.sender.send(FrameType::Text(false), &event);
+            false
+        } else {
+            true
+        }
+    });
+}
+
+fn status_snapshot(state: &AppState) -> StatusResponse {
+    StatusResponse {
+        wifi: state.wifi.clone(),
+        relay: state.relay.clone(),
+        probes: state.probes.clone(),
+        uptime_seconds: START_TIME.elapsed().as_secs(),
+        firmware: state.firmware.clone(),
+    }
+}
+
 fn handle_get_defaults(req: Request<&mut EspHttpConnection>) -> Result<(), EspError> {
     let state = APP_STATE.lock().unwrap();
     respond_json(req, 200, &state.defaults)
@@ -351,6 +888,8 @@ fn handle_set_defaults(
     state.defaults.default_state = body.default_state;
     state.defaults.hysteresis = body.hysteresis;
     state.defaults.min_on_temp = body.min_on_temp;
+    state.defaults.check_breached_passwords = body.check_breached_passwords;
+    state.defaults.min_dwell_seconds = body.min_dwell_seconds;
     respond_json(req, 200, &state.defaults)
 }
 
@@ -418,12 +957,24 @@ fn enforce_firmware_constraints(len: usize) -> Result<(), FirmwareValidationErro
     Ok(())
 }
 
-fn build_firmware_metadata(len: usize, digest: &[u8]) -> FirmwareInfo {
+fn build_firmware_metadata(
+    len: usize,
+    digest: &[u8],
+    active_slot: String,
+    pending_slot: Option<String>,
+    rollback_pending: bool,
+    signer_key_id: &'static str,
+) -> FirmwareInfo {
     FirmwareInfo {
         sha256: hex_encode(digest),
         size: len as u64,
         uploaded_at: Some(now_rfc3339()),
-        staged: false,
+        staged: pending_slot.is_some(),
+        active_slot,
+        pending_slot,
+        rollback_pending,
+        signed: true,
+        signer_key_id,
     }
 }
 
@@ -438,6 +989,37 @@ fn is_octet_stream(content_type: Option<&str>) -> bool {
         .unwrap_or(false)
 }
 
+/// Where the detached Ed25519 signature for an upload is carried: either an
+/// `X-Firmware-Signature` hex header, or a fixed 64-byte trailer appended
+/// after the image (in which case `image_len` is derived from
+/// `Content-Length`).
+enum SignatureSource {
+    Header([u8; FIRMWARE_SIGNATURE_LEN]),
+    Trailer { image_len: usize },
+}
+
+fn resolve_signature_source(
+    req: &Request<&mut EspHttpConnection>,
+) -> Result<SignatureSource, &'static str> {
+    if let Some(hex_sig) = req.header(FIRMWARE_SIGNATURE_HEADER) {
+        let bytes =
+            hex_decode(hex_sig).map_err(|_| "Invalid X-Firmware-Signature hex encoding")?;
+        let signature: [u8; FIRMWARE_SIGNATURE_LEN] = bytes
+            .try_into()
+            .map_err(|_| "X-Firmware-Signature must be 64 bytes")?;
+        return Ok(SignatureSource::Header(signature));
+    }
+
+    let content_length: usize = req
+        .header("Content-Length")
+        .and_then(|value| value.parse().ok())
+        .ok_or("Firmware upload needs an X-Firmware-Signature header or a signed trailer")?;
+    let image_len = content_length
+        .checked_sub(FIRMWARE_SIGNATURE_LEN)
+        .ok_or("Firmware upload too small to contain a trailing signature")?;
+    Ok(SignatureSource::Trailer { image_len })
+}
+
 fn handle_firmware_upload(mut req: Request<&mut EspHttpConnection>) -> Result<(), EspError> {
     if !is_octet_stream(req.header("Content-Type")) {
         return respond_error(
@@ -447,40 +1029,124 @@ fn handle_firmware_upload(mut req: Request<&mut EspHttpConnection>) -> Result<()
         );
     }
 
-    let mut payload = Vec::new();
+    let signature_source = match resolve_signature_source(&req) {
+        Ok(source) => source,
+        Err(message) => return respond_error(req, 400, message),
+    };
+
+    let mut ota = match EspOta::new() {
+        Ok(ota) => ota,
+        Err(err) => {
+            error!("Failed to open OTA handle: {err}");
+            return respond_error(req, 500, "Firmware update subsystem unavailable");
+        }
+    };
+    let mut update = match ota.initiate_update() {
+        Ok(update) => update,
+        Err(err) => {
+            error!("Failed to start OTA update: {err}");
+            return respond_error(req, 500, "Failed to start firmware update");
+        }
+    };
+
     let mut hasher = Sha256::new();
-    let mut buffer = [0u8; 4096];
+    let mut buffer = [0u8; FIRMWARE_CHUNK_SIZE];
+    let mut image_len = 0usize;
+    let mut trailer = Vec::new();
 
     loop {
         let read = req.read(&mut buffer)?;
         if read == 0 {
             break;
         }
-        let chunk = &buffer[..read];
-        hasher.update(chunk);
-        payload.extend_from_slice(chunk);
+        let mut chunk = &buffer[..read];
+
+        if let SignatureSource::Trailer { image_len: target } = signature_source {
+            if image_len < target {
+                let remaining_image = target - image_len;
+                if chunk.len() > remaining_image {
+                    let (image_part, trailer_part) = chunk.split_at(remaining_image);
+                    trailer.extend_from_slice(trailer_part);
+                    chunk = image_part;
+                }
+            } else {
+                trailer.extend_from_slice(chunk);
+                chunk = &[];
+            }
+        }
+
+        if chunk.is_empty() {
+            continue;
+        }
+
+        image_len += chunk.len();
         if let Err(error @ FirmwareValidationError::TooLarge(_)) =
-            enforce_firmware_constraints(payload.len())
+            enforce_firmware_constraints(image_len)
         {
-            warn!(
-                "Firmware upload exceeded limit: {} bytes (max {})",
-                payload.len(),
-                MAX_FIRMWARE_SIZE
-            );
+            warn!("Firmware upload exceeded limit: {image_len} bytes (max {MAX_FIRMWARE_SIZE})");
+            let _ = update.abort();
             return respond_error(req, 400, error.message());
         }
+        hasher.update(chunk);
+        if let Err(err) = update.write(chunk) {
+            error!("Failed to write firmware chunk to flash: {err}");
+            let _ = update.abort();
+            return respond_error(req, 500, "Failed to write firmware to flash");
+        }
     }
 
-    if let Err(error) = enforce_firmware_constraints(payload.len()) {
+    if let Err(error) = enforce_firmware_constraints(image_len) {
+        let _ = update.abort();
         return respond_error(req, 400, error.message());
     }
 
+    let signature: [u8; FIRMWARE_SIGNATURE_LEN] = match signature_source {
+        SignatureSource::Header(signature) => signature,
+        SignatureSource::Trailer { .. } => match trailer.try_into() {
+            Ok(signature) => signature,
+            Err(_) => {
+                let _ = update.abort();
+                return respond_error(req, 400, "Firmware signature trailer truncated");
+            }
+        },
+    };
+
     let digest = hasher.finalize();
-    let metadata = build_firmware_metadata(payload.len(), &digest);
+    let (pubkey, signer_key_id) = match trusted_firmware_pubkey() {
+        Ok(result) => result,
+        Err(err) => {
+            error!("Failed to resolve trusted firmware signer key: {err}");
+            let _ = update.abort();
+            return respond_error(req, 500, "Failed to resolve trusted firmware signer key");
+        }
+    };
+    if verify_firmware_signature(&digest, &signature, &pubkey).is_err() {
+        warn!("Firmware signature verification failed for upload");
+        let _ = update.abort();
+        return respond_error(req, 400, FirmwareValidationError::BadSignature.message());
+    }
+
+    // Only flip the new slot bootable once the signature has checked out.
+    if let Err(err) = update.complete() {
+        error!("Failed to finalize OTA update: {err}");
+        return respond_error(req, 500, "Failed to finalize firmware update");
+    }
+
+    let active_slot = slot_label(ota.get_running_slot());
+    let pending_slot = Some(slot_label(ota.get_update_slot()));
+    let rollback_pending = ota.is_rollback_pending().unwrap_or(false);
+    let metadata = build_firmware_metadata(
+        image_len,
+        &digest,
+        active_slot,
+        pending_slot,
+        rollback_pending,
+        signer_key_id,
+    );
 
     info!(
-        "Firmware upload stub received {} bytes (sha256={})",
-        metadata.size, metadata.sha256
+        "Firmware staged: {} bytes (sha256={}), active={}, pending={:?}",
+        metadata.size, metadata.sha256, metadata.active_slot, metadata.pending_slot
     );
 
     {
@@ -491,6 +1157,114 @@ fn handle_firmware_upload(mut req: Request<&mut EspHttpConnection>) -> Result<()
     respond_json(req, 200, &metadata)
 }
 
+/// Resolves the trusted signing key: an NVS-stored rotation key takes
+/// priority over the compiled-in default. Returns the key alongside an
+/// identifier recorded in `FirmwareInfo.signer_key_id`. Stored in the clear
+/// rather than encrypted: it's a *public* key, needing integrity rather than
+/// confidentiality, and it's already effectively public once it's the
+/// signer's verifying key. If a rotation key is present but unreadable or
+/// malformed, this fails loudly instead of silently trusting the compiled-in
+/// default again -- a corrupted rotation entry should block firmware
+/// verification, not quietly downgrade to an older trust root.
+fn trusted_firmware_pubkey() -> Result<(VerifyingKey, &'static str)> {
+    match read_rotation_pubkey()? {
+        Some(bytes) => {
+            let key = VerifyingKey::from_bytes(&bytes)
+                .map_err(|err| anyhow!("Stored fw_pubkey rotation key is invalid: {err}"))?;
+            Ok((key, "rotated"))
+        }
+        None => Ok((
+            VerifyingKey::from_bytes(&TRUSTED_FW_PUBKEY)
+                .expect("compiled-in firmware public key is invalid"),
+            "default",
+        )),
+    }
+}
+
+fn read_rotation_pubkey() -> Result<Option<[u8; 32]>> {
+    let mut nvs = NVS.lock().unwrap();
+    let mut buffer = [0u8; MAX_NVS_STR_LEN];
+    let Some(hex) = read_nvs_str(&mut nvs, NVS_KEY_FW_PUBKEY, &mut buffer)? else {
+        return Ok(None);
+    };
+    let bytes = hex_decode(&hex).context("Stored fw_pubkey is not valid hex")?;
+    let bytes: [u8; 32] = bytes
+        .try_into()
+        .map_err(|_| anyhow!("Stored fw_pubkey has the wrong length"))?;
+    Ok(Some(bytes))
+}
+
+/// Rotates the trusted firmware-signing public key: validates that
+/// `public_key` is a well-formed Ed25519 verifying key, then persists it to
+/// NVS under `fw_pubkey` in the clear (see `trusted_firmware_pubkey`'s doc
+/// comment for why it isn't encrypted), so `trusted_firmware_pubkey` picks it
+/// up on the next upload.
+fn handle_firmware_signer_key_rotate(
+    req: Request<&mut EspHttpConnection>,
+    body: FirmwareSignerKeyRequest,
+) -> Result<(), EspError> {
+    let Ok(key_bytes) = hex_decode(&body.public_key) else {
+        return respond_error(req, 400, "public_key must be hex-encoded");
+    };
+    let Ok(key_bytes): Result<[u8; 32], _> = key_bytes.try_into() else {
+        return respond_error(req, 400, "public_key must be 32 bytes");
+    };
+    if VerifyingKey::from_bytes(&key_bytes).is_err() {
+        return respond_error(req, 400, "public_key is not a valid Ed25519 public key");
+    }
+
+    let mut nvs = NVS.lock().unwrap();
+    let persisted = nvs
+        .set_str(NVS_KEY_FW_PUBKEY, &hex_encode(&key_bytes))
+        .and_then(|_| nvs.commit());
+    drop(nvs);
+    if let Err(err) = persisted {
+        error!("Failed to persist rotated firmware signer key: {err}");
+        return respond_error(req, 500, "Failed to persist signer key");
+    }
+
+    respond_json(req, 200, &json!({"rotated": true}))
+}
+
+fn verify_firmware_signature(
+    digest: &[u8],
+    signature_bytes: &[u8; FIRMWARE_SIGNATURE_LEN],
+    pubkey: &VerifyingKey,
+) -> Result<(), ed25519_dalek::SignatureError> {
+    let signature = Signature::from_bytes(signature_bytes);
+    pubkey.verify(digest, &signature)
+}
+
+/// Confirms the currently-running (newly-staged) firmware image so the
+/// bootloader doesn't roll back to the prior slot on the next unconfirmed
+/// boot. Wraps `esp_ota_mark_app_valid_cancel_rollback`.
+fn handle_firmware_confirm(req: Request<&mut EspHttpConnection>) -> Result<(), EspError> {
+    let mut ota = match EspOta::new() {
+        Ok(ota) => ota,
+        Err(err) => {
+            error!("Failed to open OTA handle: {err}");
+            return respond_error(req, 500, "Firmware update subsystem unavailable");
+        }
+    };
+    if let Err(err) = ota.mark_running_slot_valid() {
+        error!("Failed to confirm firmware: {err}");
+        return respond_error(req, 500, "Failed to confirm firmware");
+    }
+    respond_json(req, 200, &json!({"confirmed": true}))
+}
+
+/// Human-readable partition label for a slot lookup, falling back to
+/// "unknown" if the driver couldn't report one.
+fn slot_label(slot: Result<esp_idf_svc::ota::Slot, EspError>) -> String {
+    match slot {
+        Ok(slot) => slot.label,
+        Err(err) => {
+            warn!("Failed to read OTA slot info: {err}");
+            "unknown".into()
+        }
+    }
+}
+
 fn handle_password_change(
     mut req: Request<&mut EspHttpConnection>,
     body: PasswordChangeRequest,
@@ -502,17 +1276,96 @@ fn handle_password_change(
     if !state.credentials.verify_password(&body.current_password) {
         return respond_error(req, 401, "Current password incorrect");
     }
+
+    let check_breached = state.defaults.check_breached_passwords;
+    drop(state);
+    if check_breached {
+        if let Some(count) = check_breached_password(&body.new_password) {
+            return respond_error(
+                req,
+                400,
+                &format!("Password has appeared in {count} known data breaches"),
+            );
+        }
+    }
+    let mut state = APP_STATE.lock().unwrap();
+    if !state.credentials.verify_password(&body.current_password) {
+        return respond_error(req, 401, "Current password incorrect");
+    }
     let previous = state.credentials.clone();
+    let previous_app_key = state.app_key;
     state.credentials.set_password(&body.new_password);
-    state.credentials.invalidate_token();
-    if let Err(err) = persist_credentials_state(&state.credentials, state.provisioned) {
+    state.credentials.invalidate_all_sessions();
+    // The app key is derived from the password, so changing the password
+    // rotates in a new key; the in-memory `totp_secret` (if any) is still
+    // plaintext and gets re-encrypted under it below.
+    let persisted = rotate_app_key_locked(&body.new_password).and_then(|app_key| {
+        state.app_key = Some(app_key);
+        persist_credentials_state(&state.credentials, state.provisioned, &app_key)
+    });
+    if let Err(err) = persisted {
         error!("Failed to persist updated credentials: {err}");
         state.credentials = previous;
+        state.app_key = previous_app_key;
         return respond_error(req, 500, "Failed to persist credentials");
     }
     respond_json(req, 200, &json!({"changed": true}))
 }
 
+/// Starts enrolling a TOTP second factor, returning an `otpauth://` URI for
+/// the operator to scan. The factor isn't active until `handle_totp_confirm`
+/// receives one valid code from it.
+fn handle_totp_enroll(req: Request<&mut EspHttpConnection>) -> Result<(), EspError> {
+    let mut state = APP_STATE.lock().unwrap();
+    let provisioning_uri = state.credentials.begin_totp_enrollment();
+    respond_json(req, 200, &json!({"provisioningUri": provisioning_uri}))
+}
+
+fn handle_totp_confirm(
+    mut req: Request<&mut EspHttpConnection>,
+    body: TotpConfirmRequest,
+) -> Result<(), EspError> {
+    let mut state = APP_STATE.lock().unwrap();
+    if !state.credentials.confirm_totp_enrollment(&body.code) {
+        return respond_error(req, 401, "Invalid authenticator code");
+    }
+    // `authorize` already required a live session, which only exists after
+    // a login this boot re-derived the app key.
+    let Some(app_key) = state.app_key else {
+        error!("TOTP enrollment confirmed with no app key unlocked");
+        state.credentials.disable_totp();
+        return respond_error(req, 500, "App key unavailable; log in again and retry");
+    };
+    if let Err(err) = persist_credentials_state(&state.credentials, state.provisioned, &app_key) {
+        error!("Failed to persist TOTP enrollment: {err}");
+        state.credentials.disable_totp();
+        return respond_error(req, 500, "Failed to persist TOTP enrollment");
+    }
+    respond_json(req, 200, &json!({"enabled": true}))
+}
+
+/// Disables TOTP, requiring the current password as confirmation so a
+/// hijacked session token alone can't strip the second factor.
+fn handle_totp_disable(
+    mut req: Request<&mut EspHttpConnection>,
+    body: TotpDisableRequest,
+) -> Result<(), EspError> {
+    let mut state = APP_STATE.lock().unwrap();
+    if !state.credentials.verify_password(&body.password) {
+        return respond_error(req, 401, "Current password incorrect");
+    }
+    let Some(app_key) = state.app_key else {
+        error!("TOTP disablement attempted with no app key unlocked");
+        return respond_error(req, 500, "App key unavailable; log in again and retry");
+    };
+    state.credentials.disable_totp();
+    if let Err(err) = persist_credentials_state(&state.credentials, state.provisioned, &app_key) {
+        error!("Failed to persist TOTP disablement: {err}");
+        return respond_error(req, 500, "Failed to persist TOTP disablement");
+    }
+    respond_json(req, 200, &json!({"enabled": false}))
+}
+
 fn respond_json<T: Serialize>(
     req: Request<&mut EspHttpConnection>,
     status: u16,
@@ -549,6 +1402,41 @@ fn respond_empty(req: Request<&mut EspHttpConnection>, status: u16) -> Result<()
     Ok(())
 }
 
+/// Responds 429 for a source currently inside its `LoginThrottle` backoff
+/// window, advertising when it's safe to retry.
+fn respond_throttled(
+    mut req: Request<&mut EspHttpConnection>,
+    retry_after: Duration,
+) -> Result<(), EspError> {
+    let retry_after_secs = retry_after.as_secs().max(1);
+    warn!("Throttling login/provisioning attempt for {retry_after_secs}s");
+    let retry_after_str = retry_after_secs.to_string();
+    let body = serde_json::to_vec(&json!({
+        "error": "Too many attempts",
+        "lockedForSeconds": retry_after_secs,
+    }))
+    .unwrap_or_default();
+    let mut response = req.into_response(
+        429,
+        Some("application/json"),
+        &[("Retry-After", retry_after_str.as_str())],
+    )?;
+    response.write_all(&body)?;
+    Ok(())
+}
+
+/// Builds an auth-failure body that carries `lockedForSeconds` alongside
+/// `error` whenever this failure pushed `account_lockout` past its
+/// threshold, so the UI can show "locked for N seconds" without waiting for
+/// a follow-up 429.
+fn login_failure_body(error: &str, locked_for: Option<Duration>) -> serde_json::Value {
+    let mut body = json!({ "error": error });
+    if let Some(locked_for) = locked_for {
+        body["lockedForSeconds"] = json!(locked_for.as_secs().max(1));
+    }
+    body
+}
+
 fn parse_json<T>(req: &mut Request<&mut EspHttpConnection>) -> anyhow::Result<T>
 where
     T: DeserializeOwned,
@@ -567,11 +1455,13 @@ where
 }
 
 fn now_rfc3339() -> String {
-    let system_now = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap_or_default();
+    format_system_time(SystemTime::now())
+}
+
+fn format_system_time(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
     let nanos =
-        (system_now.as_secs() as i128) * 1_000_000_000_i128 + system_now.subsec_nanos() as i128;
+        (since_epoch.as_secs() as i128) * 1_000_000_000_i128 + since_epoch.subsec_nanos() as i128;
     let odt = OffsetDateTime::from_unix_timestamp_nanos(nanos)
         .unwrap_or_else(|_| OffsetDateTime::UNIX_EPOCH);
     odt.to_string()
@@ -590,12 +1480,130 @@ fn generate_token() -> String {
     hex_encode(&bytes)
 }
 
+/// Non-secret identifier for a session, derived from its bearer token so it
+/// can be handed back by `GET /api/admin/sessions` and used to target
+/// `DELETE /api/admin/sessions/<id>` without exposing (or accepting back) the
+/// token itself.
+fn session_id(token: &str) -> String {
+    hex_encode(&Sha256::digest(token.as_bytes()))
+}
+
 fn derive_password_hash(password: &str, salt: &[u8]) -> String {
     let mut output = [0u8; 32];
     pbkdf2_hmac::<Sha256>(password.as_bytes(), salt, PBKDF2_ITERATIONS, &mut output);
     hex_encode(&output)
 }
 
+/// AES-256-GCM key used to encrypt secrets at rest; see the `APP_KEY_*`
+/// constants and `rotate_app_key`/`unlock_app_key`.
+type AppKey = [u8; APP_KEY_LEN];
+
+fn derive_app_key(password: &str, salt: &[u8; APP_KEY_SALT_LEN]) -> AppKey {
+    let mut key = [0u8; APP_KEY_LEN];
+    pbkdf2_hmac::<Sha256>(password.as_bytes(), salt, APP_KEY_PBKDF2_ITERATIONS, &mut key);
+    key
+}
+
+/// Encrypts `plaintext` under `key` with a fresh random nonce, returning
+/// `nonce || ciphertext` ready to hex-encode into NVS.
+fn encrypt_with_app_key(key: &AppKey, plaintext: &[u8]) -> Vec<u8> {
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key));
+    let mut nonce_bytes = [0u8; AES_GCM_NONCE_LEN];
+    OsRng.fill_bytes(&mut nonce_bytes);
+    let ciphertext = cipher
+        .encrypt(Nonce::from_slice(&nonce_bytes), plaintext)
+        .expect("AES-256-GCM encryption cannot fail for a well-formed key and nonce");
+    [nonce_bytes.as_slice(), ciphertext.as_slice()].concat()
+}
+
+/// Inverse of `encrypt_with_app_key`. `None` on a wrong key, wrong nonce
+/// length, or corrupted/tampered ciphertext.
+fn decrypt_with_app_key(key: &AppKey, blob: &[u8]) -> Option<Vec<u8>> {
+    if blob.len() < AES_GCM_NONCE_LEN {
+        return None;
+    }
+    let (nonce_bytes, ciphertext) = blob.split_at(AES_GCM_NONCE_LEN);
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key));
+    cipher.decrypt(Nonce::from_slice(nonce_bytes), ciphertext).ok()
+}
+
+/// Generates a fresh salt, derives the app key from `password`, and writes
+/// the salt plus a `verify_blob` so a later boot can confirm a re-derived
+/// key before trusting it to decrypt anything. Run at provisioning and on
+/// every password change, since the key is tied to the password.
+fn rotate_app_key(nvs: &mut EspNvs<NvsDefault>, password: &str) -> Result<AppKey> {
+    let mut salt = [0u8; APP_KEY_SALT_LEN];
+    OsRng.fill_bytes(&mut salt);
+    let key = derive_app_key(password, &salt);
+    nvs.set_str(NVS_KEY_APP_KEY_SALT, &hex_encode(&salt))?;
+    let verify_blob = encrypt_with_app_key(&key, APP_KEY_VERIFY_PLAINTEXT);
+    nvs.set_str(NVS_KEY_APP_KEY_VERIFY, &hex_encode(&verify_blob))?;
+    Ok(key)
+}
+
+/// Re-derives the app key for a just-verified login password. If no salt
+/// has been stored yet (a device provisioned before encryption-at-rest
+/// existed), rotates in a fresh key now; this is the one-time migration
+/// path onto encrypted-at-rest secrets.
+fn unlock_app_key(nvs: &mut EspNvs<NvsDefault>, password: &str) -> Result<AppKey> {
+    let mut salt_buffer = [0u8; MAX_NVS_STR_LEN];
+    let Some(salt_hex) = read_nvs_str(nvs, NVS_KEY_APP_KEY_SALT, &mut salt_buffer)? else {
+        return rotate_app_key(nvs, password);
+    };
+    let salt_bytes =
+        hex_decode(&salt_hex).map_err(|err| anyhow!("Invalid app key salt encoding: {err}"))?;
+    let salt: [u8; APP_KEY_SALT_LEN] = salt_bytes
+        .try_into()
+        .map_err(|_| anyhow!("Invalid app key salt length stored in NVS"))?;
+    let key = derive_app_key(password, &salt);
+
+    let mut verify_buffer = [0u8; MAX_NVS_STR_LEN];
+    let verify_hex = read_nvs_str(nvs, NVS_KEY_APP_KEY_VERIFY, &mut verify_buffer)?
+        .ok_or_else(|| anyhow!("Missing app key verify blob"))?;
+    let verify_blob =
+        hex_decode(&verify_hex).map_err(|err| anyhow!("Invalid verify blob encoding: {err}"))?;
+    match decrypt_with_app_key(&key, &verify_blob) {
+        Some(plaintext) if plaintext == APP_KEY_VERIFY_PLAINTEXT => Ok(key),
+        _ => Err(anyhow!("App key verification failed")),
+    }
+}
+
+fn rotate_app_key_locked(password: &str) -> Result<AppKey> {
+    let mut nvs = NVS.lock().unwrap();
+    rotate_app_key(&mut nvs, password)
+}
+
+fn unlock_app_key_locked(password: &str) -> Result<AppKey> {
+    let mut nvs = NVS.lock().unwrap();
+    unlock_app_key(&mut nvs, password)
+}
+
+/// Decrypts `credentials.totp_ciphertext` into `totp_secret` using `key`,
+/// transparently migrating a legacy plaintext-at-rest secret (stored before
+/// encryption-at-rest existed, recognizable by its shorter length) into
+/// memory unchanged; it's re-encrypted the next time credentials persist.
+fn unlock_totp_secret(credentials: &mut Credentials, key: &AppKey) {
+    let Some(blob) = &credentials.totp_ciphertext else {
+        return;
+    };
+    let secret_bytes = if blob.len() == TOTP_SECRET_LEN {
+        blob.clone()
+    } else {
+        match decrypt_with_app_key(key, blob) {
+            Some(secret) => secret,
+            None => {
+                warn!("Failed to decrypt stored TOTP secret; second factor unavailable");
+                return;
+            }
+        }
+    };
+    match <[u8; TOTP_SECRET_LEN]>::try_from(secret_bytes.as_slice()) {
+        Ok(secret) => credentials.totp_secret = Some(secret),
+        Err(_) => warn!("Decrypted TOTP secret had an unexpected length"),
+    }
+    credentials.totp_ciphertext = None;
+}
+
 fn constant_time_equals(left: &str, right: &str) -> bool {
     if left.len() != right.len() {
         return false;
@@ -604,6 +1612,97 @@ fn constant_time_equals(left: &str, right: &str) -> bool {
     choice.unwrap_u8() == 1
 }
 
+/// Looks up `password` against the Have I Been Pwned k-anonymity range API
+/// (https://haveibeenpwned.com/API/v3#PwnedPasswords) and returns
+/// `Some(breach_count)` on a match. Only the 5-character SHA-1 prefix ever
+/// leaves the device; the password and its full hash never do. Fails open
+/// (returns `None`, logging a warning) on any network, timeout, or parse
+/// error, since an air-gapped install or a flaky uplink shouldn't block
+/// provisioning.
+fn check_breached_password(password: &str) -> Option<u64> {
+    let mut hasher = Sha1::new();
+    hasher.update(password.as_bytes());
+    let digest = hex_encode_upper(&hasher.finalize());
+    let (prefix, suffix) = digest.split_at(5);
+
+    match query_hibp_range(prefix) {
+        Ok(body) => body.lines().find_map(|line| {
+            let (candidate, count) = line.trim().split_once(':')?;
+            constant_time_equals(candidate, suffix)
+                .then(|| count.trim().parse().ok())
+                .flatten()
+        }),
+        Err(err) => {
+            warn!("Have I Been Pwned lookup failed, allowing password: {err}");
+            None
+        }
+    }
+}
+
+fn query_hibp_range(prefix: &str) -> anyhow::Result<String> {
+    let config = HttpClientConfiguration {
+        timeout: Some(HIBP_TIMEOUT),
+        crt_bundle_attach: Some(esp_idf_svc::sys::esp_crt_bundle_attach),
+        ..Default::default()
+    };
+    let connection = HttpClientConnection::new(&config)?;
+    let mut client = HttpClient::wrap(connection);
+    let url = format!("{HIBP_RANGE_URL}{prefix}");
+    let request = client.request(HttpMethod::Get, &url, &[])?;
+    let mut response = request.submit()?;
+
+    let mut body = Vec::new();
+    let mut buffer = [0u8; 512];
+    loop {
+        let read = response.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        body.extend_from_slice(&buffer[..read]);
+    }
+    Ok(String::from_utf8(body)?)
+}
+
+fn totp_provisioning_uri(username: &str, secret: &[u8; TOTP_SECRET_LEN]) -> String {
+    format!(
+        "otpauth://totp/{TOTP_ISSUER}:{username}?secret={}&issuer={TOTP_ISSUER}&digits={TOTP_DIGITS}&period={TOTP_STEP_SECONDS}",
+        base32_encode(secret)
+    )
+}
+
+/// Computes the RFC 6238 TOTP value for `secret` at time step `counter`
+/// (`floor(unix_seconds / TOTP_STEP_SECONDS)`) via HMAC-SHA1 and dynamic
+/// truncation.
+fn compute_totp(secret: &[u8], counter: u64) -> String {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).expect("HMAC accepts any key length");
+    mac.update(&counter.to_be_bytes());
+    let digest = mac.finalize().into_bytes();
+    let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+    let truncated = ((digest[offset] as u32 & 0x7f) << 24)
+        | (digest[offset + 1] as u32) << 16
+        | (digest[offset + 2] as u32) << 8
+        | (digest[offset + 3] as u32);
+    format!(
+        "{:0width$}",
+        truncated % 10u32.pow(TOTP_DIGITS),
+        width = TOTP_DIGITS as usize
+    )
+}
+
+/// Accepts `code` against the current time step or either neighbor, to
+/// absorb clock drift between the device and the authenticator app.
+fn verify_totp_code(secret: &[u8], code: &str) -> bool {
+    let now_step = (SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+        / TOTP_STEP_SECONDS) as i64;
+    (-TOTP_WINDOW_STEPS..=TOTP_WINDOW_STEPS).any(|delta| {
+        let step = now_step + delta;
+        step >= 0 && constant_time_equals(&compute_totp(secret, step as u64), code)
+    })
+}
+
 fn read_nvs_str(nvs: &mut EspNvs<NvsDefault>, key: &str, buffer: &mut [u8]) -> Result<Option<String>> {
     match nvs.get_str(key, buffer) {
         Ok(Some(value)) => Ok(Some(value.to_owned())),
@@ -651,24 +1750,62 @@ fn load_persistent_state() -> Result<Option<PersistentState>> {
     let mut salt = [0u8; SALT_LEN];
     salt.copy_from_slice(&salt_vec);
 
+    // The secret itself is encrypted at rest (or, on a device provisioned
+    // before that existed, still plaintext) and can't be decrypted without
+    // the app key, which in turn needs a password we don't have yet at
+    // boot. `unlock_totp_secret` decrypts this into `totp_secret` after the
+    // first successful login.
+    let mut totp_buffer = [0u8; MAX_NVS_STR_LEN];
+    let totp_ciphertext = match read_nvs_str(&mut nvs, NVS_KEY_TOTP_SECRET, &mut totp_buffer)? {
+        Some(hex) => {
+            Some(hex_decode(&hex).map_err(|err| anyhow!("Invalid TOTP secret encoding: {err}"))?)
+        }
+        None => None,
+    };
+    let totp_enabled = totp_ciphertext.is_some()
+        && matches!(nvs.get_u8(NVS_KEY_TOTP_ENABLED), Ok(Some(value)) if value != 0);
+
     Ok(Some(PersistentState {
         credentials: Credentials {
             username,
             password_hash,
             salt,
-            token: None,
+            sessions: HashMap::new(),
+            totp_secret: None,
+            totp_ciphertext,
+            totp_enabled,
+            pending_totp_secret: None,
         },
         provisioned,
     }))
 }
 
-fn persist_credentials_state(credentials: &Credentials, provisioned: bool) -> Result<()> {
+/// Persists `credentials` to NVS. `app_key` encrypts the TOTP secret at
+/// rest; every call site holds one by the time credentials can change,
+/// since establishing one (via `rotate_app_key`/`unlock_app_key`) is a
+/// prerequisite of provisioning, password change, and login.
+fn persist_credentials_state(
+    credentials: &Credentials,
+    provisioned: bool,
+    app_key: &AppKey,
+) -> Result<()> {
     let mut nvs = NVS.lock().unwrap();
     nvs.set_str(NVS_KEY_USERNAME, &credentials.username)?;
     nvs.set_str(NVS_KEY_PASSWORD_HASH, &credentials.password_hash)?;
     let salt_hex = hex_encode(&credentials.salt);
     nvs.set_str(NVS_KEY_SALT, &salt_hex)?;
     nvs.set_u8(NVS_KEY_PROVISIONED, if provisioned { 1 } else { 0 })?;
+    match credentials.totp_secret {
+        Some(secret) => {
+            let ciphertext = encrypt_with_app_key(app_key, &secret);
+            nvs.set_str(NVS_KEY_TOTP_SECRET, &hex_encode(&ciphertext))?;
+            nvs.set_u8(NVS_KEY_TOTP_ENABLED, if credentials.totp_enabled { 1 } else { 0 })?;
+        }
+        None => {
+            let _ = nvs.remove(NVS_KEY_TOTP_SECRET);
+            nvs.set_u8(NVS_KEY_TOTP_ENABLED, 0)?;
+        }
+    }
     nvs.commit()?;
     Ok(())
 }
@@ -678,6 +1815,10 @@ fn clear_persistent_credentials() -> Result<()> {
     let _ = nvs.remove(NVS_KEY_USERNAME);
     let _ = nvs.remove(NVS_KEY_PASSWORD_HASH);
     let _ = nvs.remove(NVS_KEY_SALT);
+    let _ = nvs.remove(NVS_KEY_TOTP_SECRET);
+    let _ = nvs.remove(NVS_KEY_APP_KEY_SALT);
+    let _ = nvs.remove(NVS_KEY_APP_KEY_VERIFY);
+    nvs.set_u8(NVS_KEY_TOTP_ENABLED, 0)?;
     nvs.set_u8(NVS_KEY_PROVISIONED, 0)?;
     nvs.commit()?;
     Ok(())
@@ -704,6 +1845,7 @@ enum AuthorizationFailure {
 enum FirmwareValidationError {
     Empty,
     TooLarge(usize),
+    BadSignature,
 }
 
 impl FirmwareValidationError {
@@ -711,6 +1853,7 @@ impl FirmwareValidationError {
         match self {
             FirmwareValidationError::Empty => "Firmware image cannot be empty",
             FirmwareValidationError::TooLarge(_) => "Firmware image exceeds 2 MiB limit",
+            FirmwareValidationError::BadSignature => "Firmware signature verification failed",
         }
     }
 }
@@ -782,6 +1925,13 @@ struct RelayStatus {
     state: String,
     #[serde(rename = "lastChange")]
     last_change: Option<String>,
+    /// "auto" lets `thermostat_tick` drive `state` from probe temperatures;
+    /// "manual" is set by `handle_relay` and holds until `/api/relay/auto`
+    /// hands control back to the loop.
+    mode: String,
+    /// Human-readable explanation of the most recent state change, whether
+    /// it came from the thermostat loop or a manual override.
+    reason: Option<String>,
 }
 
 #[derive(Clone, Serialize)]
@@ -800,6 +1950,101 @@ struct Defaults {
     hysteresis: u16,
     #[serde(rename = "min_on_temp")]
     min_on_temp: u16,
+    /// When set, `handle_post_provisioning` and `handle_password_change`
+    /// reject passwords found by `check_breached_password`. Air-gapped
+    /// installs with no route to the internet can flip this off.
+    check_breached_passwords: bool,
+    /// Minimum time `thermostat_tick` must leave the relay in a state before
+    /// flipping it again, to protect the pump from short-cycling.
+    min_dwell_seconds: u32,
+}
+
+/// Per-source consecutive-failure tracker guarding `/api/login` and
+/// `/api/provisioning` against password guessing. Bounded and self-expiring
+/// so it can't grow without limit on a memory-constrained device.
+#[derive(Default)]
+struct LoginThrottle {
+    attempts: HashMap<String, LoginAttempt>,
+}
+
+struct LoginAttempt {
+    failures: u32,
+    first_failure_at: SystemTime,
+    locked_until: Option<SystemTime>,
+}
+
+impl LoginThrottle {
+    /// Returns `Some(remaining)` if `source` is still within its backoff
+    /// window, in which case the caller must respond 429 without even
+    /// attempting `verify_password`.
+    fn check(&mut self, source: &str, now: SystemTime) -> Option<Duration> {
+        self.evict_stale(now);
+        let locked_until = self.attempts.get(source)?.locked_until?;
+        let remaining = locked_until.duration_since(now).ok()?;
+        (!remaining.is_zero()).then_some(remaining)
+    }
+
+    /// Records a failed attempt from `source`, escalating the backoff once
+    /// `LOGIN_ATTEMPT_THRESHOLD` consecutive failures have landed within
+    /// `LOGIN_ATTEMPT_WINDOW`.
+    fn record_failure(&mut self, source: &str, now: SystemTime) {
+        self.evict_stale(now);
+        if !self.attempts.contains_key(source) && self.attempts.len() >= MAX_LOGIN_THROTTLE_SOURCES {
+            self.evict_oldest();
+        }
+
+        let attempt = self
+            .attempts
+            .entry(source.to_owned())
+            .or_insert_with(|| LoginAttempt {
+                failures: 0,
+                first_failure_at: now,
+                locked_until: None,
+            });
+        if now.duration_since(attempt.first_failure_at).unwrap_or_default() > LOGIN_ATTEMPT_WINDOW {
+            attempt.failures = 0;
+            attempt.first_failure_at = now;
+        }
+
+        attempt.failures += 1;
+        if attempt.failures > LOGIN_ATTEMPT_THRESHOLD {
+            let doublings = attempt.failures - LOGIN_ATTEMPT_THRESHOLD - 1;
+            let backoff = LOGIN_BACKOFF_BASE
+                .checked_mul(1u32.checked_shl(doublings).unwrap_or(u32::MAX))
+                .unwrap_or(LOGIN_BACKOFF_CAP)
+                .min(LOGIN_BACKOFF_CAP);
+            attempt.locked_until = Some(now + backoff);
+        }
+    }
+
+    /// Clears `source`'s streak entirely, called on a successful login.
+    fn record_success(&mut self, source: &str) {
+        self.attempts.remove(source);
+    }
+
+    /// Drops entries that are no longer locked out and whose streak has
+    /// aged out of `LOGIN_ATTEMPT_WINDOW`.
+    fn evict_stale(&mut self, now: SystemTime) {
+        self.attempts.retain(|_, attempt| {
+            if let Some(locked_until) = attempt.locked_until {
+                if locked_until > now {
+                    return true;
+                }
+            }
+            now.duration_since(attempt.first_failure_at).unwrap_or_default() <= LOGIN_ATTEMPT_WINDOW
+        });
+    }
+
+    fn evict_oldest(&mut self) {
+        if let Some(oldest) = self
+            .attempts
+            .iter()
+            .min_by_key(|(_, attempt)| attempt.first_failure_at)
+            .map(|(source, _)| source.clone())
+        {
+            self.attempts.remove(&oldest);
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -807,7 +2052,21 @@ struct Credentials {
     username: String,
     password_hash: String,
     salt: [u8; SALT_LEN],
-    token: Option<SessionToken>,
+    sessions: HashMap<String, Session>,
+    /// Active TOTP secret, if the second factor has been enrolled and
+    /// confirmed. `None` means login only requires the password, or that
+    /// the device has rebooted and `totp_ciphertext` hasn't been unlocked
+    /// by a login yet.
+    totp_secret: Option<[u8; TOTP_SECRET_LEN]>,
+    /// Encrypted-at-rest form of `totp_secret` as loaded from NVS, not yet
+    /// decrypted because doing so needs the app key. Cleared once
+    /// `unlock_totp_secret` successfully populates `totp_secret`.
+    totp_ciphertext: Option<Vec<u8>>,
+    totp_enabled: bool,
+    /// Secret generated by `begin_totp_enrollment`, awaiting one valid code
+    /// from `confirm_totp_enrollment` before it replaces `totp_secret`.
+    /// Not persisted: a reboot mid-enrollment just means enrolling again.
+    pending_totp_secret: Option<[u8; TOTP_SECRET_LEN]>,
 }
 
 #[derive(Clone, Serialize)]
@@ -829,21 +2088,42 @@ struct FirmwareInfo {
     #[serde(rename = "uploadedAt")]
     uploaded_at: Option<String>,
     staged: bool,
-}
-
+    #[serde(rename = "activeSlot")]
+    active_slot: String,
+    #[serde(rename = "pendingSlot")]
+    pending_slot: Option<String>,
+    #[serde(rename = "rollbackPending")]
+    rollback_pending: bool,
+    /// Always `true` in practice: `handle_firmware_upload` aborts the update
+    /// before this is ever recorded if `verify_firmware_signature` fails.
+    /// Kept explicit so `StatusResponse` doesn't imply authenticity the
+    /// backend hasn't actually checked.
+    signed: bool,
+    #[serde(rename = "signerKeyId")]
+    signer_key_id: &'static str,
+}
+
+/// One logged-in device. Keyed by its opaque bearer token in
+/// `Credentials::sessions`, so multiple devices can hold independent,
+/// independently-revocable sessions instead of one shared token.
 #[derive(Clone)]
-struct SessionToken {
-    value: String,
-    issued_at: SystemTime,
+struct Session {
+    /// Non-secret identifier derived from the bearer token (see
+    /// `session_id`), safe to hand back from `GET /api/admin/sessions` and
+    /// to revoke by, unlike the token itself.
+    id: String,
+    created_at: SystemTime,
     last_seen: SystemTime,
+    label: String,
 }
 
-impl SessionToken {
-    fn new(value: String, now: SystemTime) -> Self {
+impl Session {
+    fn new(now: SystemTime, label: String, id: String) -> Self {
         Self {
-            value,
-            issued_at: now,
+            id,
+            created_at: now,
             last_seen: now,
+            label,
         }
     }
 
@@ -869,11 +2149,44 @@ enum TokenValidation {
 struct AppState {
     wifi: WifiStatus,
     relay: RelayStatus,
+    /// When the relay last changed state, tracked separately from
+    /// `relay.last_change`'s display string so `thermostat_tick` can compare
+    /// elapsed dwell time without reparsing it.
+    relay_last_change_at: Option<SystemTime>,
     defaults: Defaults,
     probes: Vec<ProbeInfo>,
     firmware: Option<FirmwareInfo>,
     credentials: Credentials,
     provisioned: bool,
+    ws_clients: Vec<WsClient>,
+    /// Last controlling-probe temperature a `probe` WS event was broadcast
+    /// for, so `thermostat_tick` only pushes one when it moves by more than
+    /// `PROBE_BROADCAST_EPSILON_F`.
+    last_broadcast_probe_temperature: Option<f32>,
+    /// Guards `/api/login` and `/api/provisioning` against password
+    /// guessing; see `LoginThrottle`.
+    login_throttle: LoginThrottle,
+    /// Per-username companion to `login_throttle`: tracks failed `/api/login`
+    /// attempts against the account itself, so rotating source IPs (or a
+    /// client behind a proxy that never forwards one) can't bypass the
+    /// source-keyed lockout above.
+    account_lockout: LoginThrottle,
+    /// Encryption-at-rest key for secrets that don't need to be readable
+    /// before someone authenticates (currently just the TOTP seed).
+    /// `None` until a successful login re-derives it from the password;
+    /// see `unlock_app_key`.
+    app_key: Option<AppKey>,
+}
+
+/// A subscriber to `/api/ws`, holding a detached sender so the HTTP server's
+/// background tasks (relay toggles, the heartbeat) can push frames outside
+/// of the connection's own handler callback.
+struct WsClient {
+    sender: esp_idf_svc::http::server::ws::EspHttpWsDetachedSender,
+    /// Bearer token the client authorized with, checked by
+    /// `expire_stale_ws_sessions` so an idle session gets disconnected even
+    /// while its socket is still open.
+    token: String,
 }
 
 struct PersistentState {
@@ -898,6 +2211,8 @@ impl Default for RelayStatus {
         Self {
             state: "off".into(),
             last_change: None,
+            mode: "auto".into(),
+            reason: None,
         }
     }
 }
@@ -908,6 +2223,8 @@ impl Default for Defaults {
             default_state: "off".into(),
             hysteresis: 2,
             min_on_temp: 70,
+            check_breached_passwords: true,
+            min_dwell_seconds: 5 * 60,
         }
     }
 }
@@ -926,7 +2243,11 @@ impl Credentials {
             username: username.into(),
             password_hash,
             salt,
-            token: None,
+            sessions: HashMap::new(),
+            totp_secret: None,
+            totp_ciphertext: None,
+            totp_enabled: false,
+            pending_totp_secret: None,
         }
     }
 
@@ -940,32 +2261,120 @@ impl Credentials {
         self.password_hash = derive_password_hash(new_password, &self.salt);
     }
 
-    fn issue_token(&mut self) -> String {
+    /// Starts a new session for `label` (a coarse client description) and
+    /// returns its bearer token, evicting the oldest session if we're over
+    /// `MAX_SESSIONS_PER_CREDENTIAL`.
+    fn issue_token(&mut self, label: String) -> String {
         let token_value = generate_token();
         let now = SystemTime::now();
-        self.token = Some(SessionToken::new(token_value.clone(), now));
+        let id = session_id(&token_value);
+        self.sessions
+            .insert(token_value.clone(), Session::new(now, label, id));
+        self.evict_oldest_if_over_cap();
         token_value
     }
 
-    fn invalidate_token(&mut self) {
-        self.token = None;
+    fn evict_oldest_if_over_cap(&mut self) {
+        while self.sessions.len() > MAX_SESSIONS_PER_CREDENTIAL {
+            let Some(oldest) = self
+                .sessions
+                .iter()
+                .min_by_key(|(_, session)| session.last_seen)
+                .map(|(token, _)| token.clone())
+            else {
+                break;
+            };
+            self.sessions.remove(&oldest);
+        }
+    }
+
+    /// Revokes a single session by its token, leaving other devices' sessions
+    /// untouched.
+    fn remove_session(&mut self, token: &str) {
+        self.sessions.remove(token);
+    }
+
+    /// Revokes a single session by its non-secret `Session::id` (as exposed
+    /// by `GET /api/admin/sessions`), rather than its bearer token.
+    fn remove_session_by_id(&mut self, id: &str) {
+        self.sessions.retain(|_, session| session.id != id);
+    }
+
+    /// Revokes every session except `keep_token`, e.g. "log out all other
+    /// devices" from the session list.
+    fn remove_other_sessions(&mut self, keep_token: &str) {
+        self.sessions.retain(|token, _| token == keep_token);
+    }
+
+    /// Revokes every session, e.g. after a password change.
+    fn invalidate_all_sessions(&mut self) {
+        self.sessions.clear();
     }
 
     fn validate_token(&mut self, candidate: &str, now: SystemTime) -> TokenValidation {
-        match self.token {
-            Some(ref mut session) => {
-                if !constant_time_equals(&session.value, candidate) {
-                    return TokenValidation::Invalid;
-                }
-                if session.is_expired(now) {
-                    self.invalidate_token();
-                    TokenValidation::Expired
-                } else {
-                    session.touch(now);
-                    TokenValidation::Authorized
-                }
+        let Some(session) = self.sessions.get_mut(candidate) else {
+            return TokenValidation::Invalid;
+        };
+        if session.is_expired(now) {
+            self.sessions.remove(candidate);
+            TokenValidation::Expired
+        } else {
+            session.touch(now);
+            TokenValidation::Authorized
+        }
+    }
+
+    /// Like `validate_token`, but doesn't `touch()` the session: used for the
+    /// `/api/ws` heartbeat's liveness sweep, where merely having a socket
+    /// open shouldn't by itself keep an otherwise-idle session alive.
+    fn session_expired(&mut self, candidate: &str, now: SystemTime) -> bool {
+        match self.sessions.get(candidate) {
+            None => true,
+            Some(session) if session.is_expired(now) => {
+                self.sessions.remove(candidate);
+                true
             }
-            None => TokenValidation::Invalid,
+            Some(_) => false,
+        }
+    }
+
+    /// Generates a fresh TOTP secret and stashes it as `pending_totp_secret`
+    /// until `confirm_totp_enrollment` proves the operator can produce a
+    /// valid code from it. Returns the `otpauth://` provisioning URI for QR
+    /// display.
+    fn begin_totp_enrollment(&mut self) -> String {
+        let mut secret = [0u8; TOTP_SECRET_LEN];
+        OsRng.fill_bytes(&mut secret);
+        self.pending_totp_secret = Some(secret);
+        totp_provisioning_uri(&self.username, &secret)
+    }
+
+    /// Verifies `code` against the pending secret and, on success, activates
+    /// it as the account's TOTP factor.
+    fn confirm_totp_enrollment(&mut self, code: &str) -> bool {
+        let Some(secret) = self.pending_totp_secret else {
+            return false;
+        };
+        if verify_totp_code(&secret, code) {
+            self.totp_secret = Some(secret);
+            self.totp_enabled = true;
+            self.pending_totp_secret = None;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn disable_totp(&mut self) {
+        self.totp_secret = None;
+        self.totp_enabled = false;
+        self.pending_totp_secret = None;
+    }
+
+    fn verify_totp(&self, code: &str) -> bool {
+        match &self.totp_secret {
+            Some(secret) => verify_totp_code(secret, code),
+            None => false,
         }
     }
 }
@@ -984,6 +2393,7 @@ impl Default for AppState {
         Self {
             wifi: WifiStatus::default(),
             relay: RelayStatus::default(),
+            relay_last_change_at: None,
             defaults: Defaults::default(),
             probes: vec![
                 ProbeInfo {
@@ -1004,6 +2414,11 @@ impl Default for AppState {
             firmware: None,
             credentials,
             provisioned,
+            ws_clients: Vec::new(),
+            last_broadcast_probe_temperature: None,
+            login_throttle: LoginThrottle::default(),
+            account_lockout: LoginThrottle::default(),
+            app_key: None,
         }
     }
 }
@@ -1012,6 +2427,8 @@ impl Default for AppState {
 struct LoginRequest {
     username: String,
     password: String,
+    /// Required once `Credentials::totp_enabled` is set.
+    totp: Option<String>,
 }
 
 #[derive(Deserialize)]
@@ -1024,6 +2441,8 @@ struct DefaultsRequest {
     default_state: String,
     hysteresis: u16,
     min_on_temp: u16,
+    check_breached_passwords: bool,
+    min_dwell_seconds: u32,
 }
 
 #[derive(Deserialize)]
@@ -1038,6 +2457,21 @@ struct PasswordChangeRequest {
     new_password: String,
 }
 
+#[derive(Deserialize)]
+struct TotpConfirmRequest {
+    code: String,
+}
+
+#[derive(Deserialize)]
+struct TotpDisableRequest {
+    password: String,
+}
+
+#[derive(Deserialize)]
+struct FirmwareSignerKeyRequest {
+    public_key: String,
+}
+
 #[derive(Deserialize)]
 struct ProvisioningRequest {
     username: Option<String>,
@@ -1053,10 +2487,10 @@ mod tests {
     #[test]
     fn session_tokens_expire_after_idle_timeout() {
         let mut credentials = Credentials::with_password("tester", "password123");
-        let token_value = credentials.issue_token();
+        let token_value = credentials.issue_token("test-client".into());
 
-        if let Some(ref mut token) = credentials.token {
-            token.last_seen = token
+        if let Some(session) = credentials.sessions.get_mut(&token_value) {
+            session.last_seen = session
                 .last_seen
                 .checked_sub(SESSION_IDLE_TIMEOUT + Duration::from_secs(1))
                 .expect("time underflow");
@@ -1064,13 +2498,13 @@ mod tests {
 
         let result = credentials.validate_token(&token_value, SystemTime::now());
         assert_eq!(result, TokenValidation::Expired);
-        assert!(credentials.token.is_none());
+        assert!(!credentials.sessions.contains_key(&token_value));
     }
 
     #[test]
     fn session_tokens_touch_on_activity() {
         let mut credentials = Credentials::with_password("tester", "password123");
-        let token_value = credentials.issue_token();
+        let token_value = credentials.issue_token("test-client".into());
         let probe_time = SystemTime::now()
             .checked_add(Duration::from_secs(60))
             .expect("time overflow");
@@ -1079,13 +2513,35 @@ mod tests {
         assert_eq!(result, TokenValidation::Authorized);
 
         let last_seen = credentials
-            .token
-            .as_ref()
-            .expect("token should remain active")
+            .sessions
+            .get(&token_value)
+            .expect("session should remain active")
             .last_seen;
         assert!(last_seen.duration_since(probe_time).is_ok());
     }
 
+    #[test]
+    fn oldest_session_evicted_when_over_cap() {
+        let mut credentials = Credentials::with_password("tester", "password123");
+        let mut tokens = Vec::new();
+        for i in 0..MAX_SESSIONS_PER_CREDENTIAL {
+            tokens.push(credentials.issue_token(format!("client-{i}")));
+        }
+        // Force the first session to look oldest regardless of clock resolution.
+        if let Some(session) = credentials.sessions.get_mut(&tokens[0]) {
+            session.last_seen = session
+                .last_seen
+                .checked_sub(Duration::from_secs(60))
+                .expect("time underflow");
+        }
+
+        let newest = credentials.issue_token("client-newest".into());
+
+        assert_eq!(credentials.sessions.len(), MAX_SESSIONS_PER_CREDENTIAL);
+        assert!(!credentials.sessions.contains_key(&tokens[0]));
+        assert!(credentials.sessions.contains_key(&newest));
+    }
+
     #[test]
     fn firmware_constraints_enforce_limits() {
         assert_eq!(
@@ -1109,11 +2565,170 @@ mod tests {
         hasher.update(payload);
         let digest = hasher.finalize();
 
-        let metadata = build_firmware_metadata(payload.len(), &digest);
+        let metadata = build_firmware_metadata(
+            payload.len(),
+            &digest,
+            "ota_0".into(),
+            Some("ota_1".into()),
+            true,
+            "default",
+        );
 
         assert_eq!(metadata.size, payload.len() as u64);
         assert_eq!(metadata.sha256, hex_encode(&digest));
         assert!(metadata.uploaded_at.is_some());
-        assert!(!metadata.staged);
+        assert!(metadata.staged);
+        assert_eq!(metadata.active_slot, "ota_0");
+        assert_eq!(metadata.pending_slot.as_deref(), Some("ota_1"));
+        assert!(metadata.rollback_pending);
+        assert!(metadata.signed);
+        assert_eq!(metadata.signer_key_id, "default");
+    }
+
+    #[test]
+    fn totp_matches_rfc6238_test_vector() {
+        // RFC 6238 Appendix B, SHA-1 case, T = 59 seconds -> counter 1.
+        // The RFC's reference 8-digit code is 94287082; mod 10^6 gives ours.
+        let secret = b"12345678901234567890";
+        assert_eq!(compute_totp(secret, 1), "287082");
+    }
+
+    #[test]
+    fn totp_enrollment_requires_confirmation_before_activating() {
+        let mut credentials = Credentials::with_password("tester", "password123");
+        let uri = credentials.begin_totp_enrollment();
+        assert!(uri.starts_with("otpauth://totp/"));
+        assert!(!credentials.totp_enabled);
+
+        let secret = credentials
+            .pending_totp_secret
+            .expect("enrollment should stash a pending secret");
+        let now_step = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+            / TOTP_STEP_SECONDS;
+        let code = compute_totp(&secret, now_step);
+
+        assert!(credentials.confirm_totp_enrollment(&code));
+        assert!(credentials.totp_enabled);
+        assert!(credentials.pending_totp_secret.is_none());
+        assert!(credentials.verify_totp(&code));
+    }
+
+    #[test]
+    fn controlling_probe_temperature_skips_disabled_probes() {
+        let probes = vec![
+            ProbeInfo {
+                id: "disabled".into(),
+                name: None,
+                fahrenheit: Some(40.0),
+                last_updated: None,
+                enabled: false,
+            },
+            ProbeInfo {
+                id: "enabled".into(),
+                name: None,
+                fahrenheit: Some(75.5),
+                last_updated: None,
+                enabled: true,
+            },
+        ];
+
+        assert_eq!(controlling_probe_temperature(&probes), Some(75.5));
+        assert_eq!(controlling_probe_temperature(&[]), None);
+    }
+
+    #[test]
+    fn login_throttle_locks_out_after_threshold_failures() {
+        let mut throttle = LoginThrottle::default();
+        let now = SystemTime::now();
+        for _ in 0..LOGIN_ATTEMPT_THRESHOLD {
+            throttle.record_failure("1.2.3.4", now);
+        }
+        assert_eq!(throttle.check("1.2.3.4", now), None);
+
+        throttle.record_failure("1.2.3.4", now);
+        let remaining = throttle
+            .check("1.2.3.4", now)
+            .expect("should be locked out past the threshold");
+        assert!(remaining <= LOGIN_BACKOFF_BASE);
+        assert!(!remaining.is_zero());
+    }
+
+    #[test]
+    fn login_throttle_backoff_doubles_and_caps() {
+        let mut throttle = LoginThrottle::default();
+        let now = SystemTime::now();
+
+        for _ in 0..=LOGIN_ATTEMPT_THRESHOLD {
+            throttle.record_failure("1.2.3.4", now);
+        }
+        let first_backoff = throttle
+            .attempts
+            .get("1.2.3.4")
+            .and_then(|attempt| attempt.locked_until)
+            .expect("should be locked out")
+            .duration_since(now)
+            .expect("locked_until should be in the future");
+        assert_eq!(first_backoff, LOGIN_BACKOFF_BASE);
+
+        throttle.record_failure("1.2.3.4", now);
+        let second_backoff = throttle
+            .attempts
+            .get("1.2.3.4")
+            .and_then(|attempt| attempt.locked_until)
+            .expect("should still be locked out")
+            .duration_since(now)
+            .expect("locked_until should be in the future");
+        assert_eq!(second_backoff, LOGIN_BACKOFF_BASE * 2);
+
+        for _ in 0..10 {
+            throttle.record_failure("1.2.3.4", now);
+        }
+        let capped_backoff = throttle
+            .attempts
+            .get("1.2.3.4")
+            .and_then(|attempt| attempt.locked_until)
+            .expect("should still be locked out")
+            .duration_since(now)
+            .expect("locked_until should be in the future");
+        assert_eq!(capped_backoff, LOGIN_BACKOFF_CAP);
+    }
+
+    #[test]
+    fn login_throttle_keys_are_independent() {
+        // The same LoginThrottle type backs both the per-source and the
+        // per-account lockout in `AppState`; either way it must track each
+        // key (an IP-ish source string, or a username) in complete
+        // isolation from the others.
+        let mut throttle = LoginThrottle::default();
+        let now = SystemTime::now();
+        for _ in 0..=LOGIN_ATTEMPT_THRESHOLD {
+            throttle.record_failure("1.2.3.4", now);
+        }
+
+        assert!(throttle.check("1.2.3.4", now).is_some());
+        assert_eq!(throttle.check("5.6.7.8", now), None);
+        assert_eq!(throttle.check("some-username", now), None);
+    }
+
+    #[test]
+    fn login_throttle_evicts_oldest_source_when_over_cap() {
+        let mut throttle = LoginThrottle::default();
+        let now = SystemTime::now();
+        for i in 0..MAX_LOGIN_THROTTLE_SOURCES {
+            let source = format!("source-{i}");
+            let at = now + Duration::from_secs(i as u64);
+            throttle.record_failure(&source, at);
+        }
+        assert_eq!(throttle.attempts.len(), MAX_LOGIN_THROTTLE_SOURCES);
+
+        let newest_at = now + Duration::from_secs(MAX_LOGIN_THROTTLE_SOURCES as u64);
+        throttle.record_failure("source-new", newest_at);
+
+        assert_eq!(throttle.attempts.len(), MAX_LOGIN_THROTTLE_SOURCES);
+        assert!(!throttle.attempts.contains_key("source-0"));
+        assert!(throttle.attempts.contains_key("source-new"));
     }
 }
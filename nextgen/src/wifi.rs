@@ -1,9 +1,79 @@
-use std::sync::Mutex;
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
 
 use anyhow::Result;
 use log::{error, warn};
 use once_cell::sync::{Lazy, OnceCell};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+
+/// Gateway address handed to SoftAP clients; also where the captive-portal
+/// DNS responder listens and where the A record it hands out points, so
+/// every hostname resolves straight to the firmware's own HTTP server.
+const CAPTIVE_PORTAL_GATEWAY: &str = "192.168.71.1";
+/// Standard DNS port the captive-portal responder binds to.
+const CAPTIVE_DNS_PORT: u16 = 53;
+
+/// Starting delay for `sta_reconnect_delay`, doubled on each consecutive
+/// STA failure.
+const STA_RECONNECT_BASE_DELAY: Duration = Duration::from_secs(1);
+/// Cap on `sta_reconnect_delay`, regardless of how many failures precede it.
+const STA_RECONNECT_MAX_DELAY: Duration = Duration::from_secs(60);
+/// Consecutive STA failures after which `reconnect_loop` gives up and falls
+/// back to the captive AP instead of retrying again.
+const STA_RECONNECT_MAX_ATTEMPTS: u32 = 5;
+/// How long a failed-to-associate network is penalized in `score_candidate`.
+const STA_RECENT_FAILURE_PENALTY_WINDOW: Duration = Duration::from_secs(5 * 60);
+/// RSSI-equivalent penalty (dBm) applied to a network that failed to
+/// associate within `STA_RECENT_FAILURE_PENALTY_WINDOW`.
+const STA_RECENT_FAILURE_PENALTY: i32 = 20;
+
+/// Returns the backoff delay before the `attempt`th (1-indexed) STA
+/// reconnect try: doubles from `STA_RECONNECT_BASE_DELAY`, capped at
+/// `STA_RECONNECT_MAX_DELAY`, with up to 20% jitter so multiple devices
+/// recovering from the same outage don't all retry in lockstep.
+fn sta_reconnect_delay(attempt: u32) -> Duration {
+    let doublings = attempt.saturating_sub(1).min(31);
+    let base = STA_RECONNECT_BASE_DELAY
+        .checked_mul(1u32.checked_shl(doublings).unwrap_or(u32::MAX))
+        .unwrap_or(STA_RECONNECT_MAX_DELAY)
+        .min(STA_RECONNECT_MAX_DELAY);
+    let jitter_fraction = (pseudo_random_u32() % 20) as f64 / 100.0;
+    base + Duration::from_secs_f64(base.as_secs_f64() * jitter_fraction)
+}
+
+/// Minimal dependency-free source of randomness for reconnect jitter: not
+/// cryptographic, just needs to avoid lockstep retries across devices.
+fn pseudo_random_u32() -> u32 {
+    use std::time::{SystemTime, UNIX_EPOCH};
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.subsec_nanos())
+        .unwrap_or(0)
+}
+
+/// Multi-radio APs broadcast the same SSID on several BSSIDs, which would
+/// otherwise show up as duplicate entries; keep only the strongest-RSSI
+/// BSSID per SSID and sort the result so the strongest networks sort first
+/// in the provisioning UI.
+fn dedupe_strongest_per_ssid(networks: Vec<WifiNetwork>) -> Vec<WifiNetwork> {
+    let mut strongest: HashMap<String, WifiNetwork> = HashMap::new();
+    for network in networks {
+        strongest
+            .entry(network.ssid.clone())
+            .and_modify(|existing| {
+                if network.rssi > existing.rssi {
+                    *existing = network.clone();
+                }
+            })
+            .or_insert(network);
+    }
+    let mut deduped: Vec<WifiNetwork> = strongest.into_values().collect();
+    deduped.sort_by_key(|network| std::cmp::Reverse(network.rssi));
+    deduped
+}
 
 /// High-level provisioning states reported to the UI.
 #[derive(Clone, Copy, Debug, Serialize, PartialEq, Eq)]
@@ -60,6 +130,14 @@ pub struct WifiSnapshot {
     pub access_point: Option<AccessPointSnapshot>,
     #[serde(rename = "provisioningState")]
     pub provisioning_state: ProvisioningState,
+    /// Consecutive STA connection failures since the last success; see
+    /// `WifiController::record_sta_failure`.
+    #[serde(rename = "retryCount")]
+    pub retry_count: u32,
+    /// Milliseconds until `reconnect_loop`'s next STA attempt, if one is
+    /// scheduled.
+    #[serde(rename = "nextAttemptInMs", skip_serializing_if = "Option::is_none")]
+    pub next_attempt_in_ms: Option<u64>,
 }
 
 impl Default for WifiSnapshot {
@@ -69,6 +147,8 @@ impl Default for WifiSnapshot {
             station: StationSnapshot::default(),
             access_point: Some(AccessPointSnapshot::default()),
             provisioning_state: ProvisioningState::ApMode,
+            retry_count: 0,
+            next_attempt_in_ms: None,
         }
     }
 }
@@ -86,12 +166,46 @@ impl Default for WifiMode {
     }
 }
 
+/// Security protocol reported by the driver for a scanned network, or
+/// chosen when joining one. Mirrors `embedded_svc::wifi::AuthMethod`'s
+/// personal/enterprise variants so the UI can show the right lock icon
+/// without the backend collapsing it down to a bare `secure: bool`.
+///
+/// `Wpa2Enterprise` is plumbing only: it scans and saves correctly, but
+/// `hardware::build_client_config` refuses to join one (no EAP identity/
+/// credential exchange with the driver is implemented yet).
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum WifiAuthMethod {
+    Open,
+    Wep,
+    Wpa,
+    Wpa2Personal,
+    Wpa2Wpa3Personal,
+    Wpa3Personal,
+    Wpa2Enterprise,
+}
+
 /// Representation of a scanned network that we can serialize directly.
 #[derive(Clone, Debug, Serialize)]
 pub struct WifiNetwork {
     pub ssid: String,
     pub rssi: i32,
-    pub secure: bool,
+    pub auth: WifiAuthMethod,
+    pub channel: u8,
+    #[serde(serialize_with = "serialize_bssid")]
+    pub bssid: [u8; 6],
+}
+
+/// Serializes a BSSID as colon-separated hex (`aa:bb:cc:dd:ee:ff`) instead
+/// of a raw byte array, matching how MAC addresses are normally displayed.
+fn serialize_bssid<S: serde::Serializer>(bssid: &[u8; 6], serializer: S) -> Result<S::Ok, S::Error> {
+    let formatted = bssid
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect::<Vec<_>>()
+        .join(":");
+    serializer.serialize_str(&formatted)
 }
 
 #[derive(Clone, Debug, Serialize)]
@@ -99,10 +213,56 @@ pub struct WifiScanResponse {
     pub networks: Vec<WifiNetwork>,
 }
 
+/// A saved Wi-Fi credential, persisted via `hardware::save_saved_networks`
+/// and tried on boot by `auto_join_saved_network`. Lower `priority` values
+/// are preferred; ties between in-range networks break on RSSI.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SavedNetwork {
+    pub ssid: String,
+    pub password: Option<String>,
+    pub priority: u8,
+    /// Auth method to join with. Defaults to `Wpa2Personal` so entries
+    /// saved before this field existed still round-trip.
+    #[serde(default = "default_saved_network_auth")]
+    pub auth: WifiAuthMethod,
+    /// Username/identity for `Wpa2Enterprise` networks; unused otherwise.
+    /// Saved and round-tripped, but see `WifiAuthMethod`'s doc comment:
+    /// enterprise join isn't implemented, so this can't be used to connect
+    /// yet.
+    #[serde(default)]
+    pub identity: Option<String>,
+}
+
+fn default_saved_network_auth() -> WifiAuthMethod {
+    WifiAuthMethod::Wpa2Personal
+}
+
+/// A `SavedNetwork` with its password redacted, returned by
+/// `WifiController::list_saved_networks` for UI display.
+#[derive(Clone, Debug, Serialize)]
+pub struct SavedNetworkSummary {
+    pub ssid: String,
+    #[serde(rename = "hasPassword")]
+    pub has_password: bool,
+    pub priority: u8,
+}
+
 #[derive(Default)]
 struct WifiRuntime {
     snapshot: WifiSnapshot,
     scan_cache: Vec<WifiNetwork>,
+    /// Consecutive STA connection failures since the last success; drives
+    /// `reconnect_loop`'s backoff and eventual captive-AP fallback.
+    sta_retry_count: u32,
+    /// When `reconnect_loop`'s next attempt is scheduled, so `snapshot()`
+    /// can report "next attempt in N ms".
+    next_attempt_at: Option<Instant>,
+    /// Most recent failure time per SSID, so `score_candidate` can penalize
+    /// networks that recently failed to associate.
+    recent_failures: HashMap<String, Instant>,
+    /// Saved network credentials, loaded from NVS at startup; see
+    /// `hardware::load_saved_networks`.
+    saved_networks: Vec<SavedNetwork>,
 }
 
 impl WifiRuntime {
@@ -113,14 +273,25 @@ impl WifiRuntime {
                 WifiNetwork {
                     ssid: "Backyard".into(),
                     rssi: -55,
-                    secure: true,
+                    auth: WifiAuthMethod::Wpa2Personal,
+                    channel: 6,
+                    bssid: [0x02, 0x00, 0x00, 0x00, 0x00, 0x01],
                 },
                 WifiNetwork {
                     ssid: "Guest".into(),
                     rssi: -68,
-                    secure: false,
+                    auth: WifiAuthMethod::Open,
+                    channel: 11,
+                    bssid: [0x02, 0x00, 0x00, 0x00, 0x00, 0x02],
                 },
             ],
+            sta_retry_count: 0,
+            next_attempt_at: None,
+            recent_failures: HashMap::new(),
+            saved_networks: hardware::load_saved_networks().unwrap_or_else(|err| {
+                warn!("Failed to load saved Wi-Fi networks: {err}");
+                Vec::new()
+            }),
         }
     }
 }
@@ -138,9 +309,16 @@ impl WifiController {
         }
     }
 
-    /// Returns a copy of the current status.
+    /// Returns a copy of the current status, overlaid with the live
+    /// reconnect-progress fields tracked separately in `WifiRuntime`.
     pub fn snapshot(&self) -> WifiSnapshot {
-        self.inner.lock().unwrap().snapshot.clone()
+        let guard = self.inner.lock().unwrap();
+        let mut snapshot = guard.snapshot.clone();
+        snapshot.retry_count = guard.sta_retry_count;
+        snapshot.next_attempt_in_ms = guard
+            .next_attempt_at
+            .map(|at| at.saturating_duration_since(Instant::now()).as_millis() as u64);
+        snapshot
     }
 
     /// Marks the beginning of a STA join attempt for the provided SSID.
@@ -155,7 +333,9 @@ impl WifiController {
         guard.snapshot.access_point = None;
     }
 
-    /// Records a successful STA join.
+    /// Records a successful STA join, tearing down the captive-portal DNS
+    /// responder now that clients can reach the real internet again, and
+    /// resetting the reconnect backoff state.
     pub fn mark_sta_connected(&self, ssid: &str, rssi: Option<i32>, ip: Option<String>) {
         let mut guard = self.inner.lock().unwrap();
         guard.snapshot.mode = WifiMode::Station;
@@ -164,9 +344,140 @@ impl WifiController {
         guard.snapshot.station.connected = true;
         guard.snapshot.station.rssi = rssi;
         guard.snapshot.station.ip = ip;
+        guard.sta_retry_count = 0;
+        guard.next_attempt_at = None;
+        drop(guard);
+
+        hardware::stop_captive_dns();
+    }
+
+    /// Records a failed STA attempt against `ssid` for backoff and scoring
+    /// purposes, and returns the new consecutive-failure count.
+    fn record_sta_failure(&self, ssid: &str) -> u32 {
+        let mut guard = self.inner.lock().unwrap();
+        guard.sta_retry_count += 1;
+        guard.recent_failures.insert(ssid.to_owned(), Instant::now());
+        guard.sta_retry_count
+    }
+
+    /// Records when `reconnect_loop`'s next attempt will fire.
+    fn schedule_next_attempt(&self, delay: Duration) {
+        self.inner.lock().unwrap().next_attempt_at = Some(Instant::now() + delay);
+    }
+
+    /// Scores `ssid` for STA candidate selection: a network not currently
+    /// visible in `scan_cache` scores lowest (`i32::MIN`); a visible one
+    /// scores by RSSI (stronger is better), penalized by
+    /// `STA_RECENT_FAILURE_PENALTY` if it failed to associate within
+    /// `STA_RECENT_FAILURE_PENALTY_WINDOW`.
+    fn score_candidate(&self, ssid: &str) -> i32 {
+        let guard = self.inner.lock().unwrap();
+        let Some(network) = guard.scan_cache.iter().find(|network| network.ssid == ssid) else {
+            return i32::MIN;
+        };
+        let mut score = network.rssi;
+        if let Some(failed_at) = guard.recent_failures.get(ssid) {
+            if failed_at.elapsed() < STA_RECENT_FAILURE_PENALTY_WINDOW {
+                score -= STA_RECENT_FAILURE_PENALTY;
+            }
+        }
+        score
+    }
+
+    /// Picks the best STA candidate among `candidates` by `score_candidate`,
+    /// skipping any that aren't currently visible at all.
+    pub fn select_best_candidate<'a, I>(&self, candidates: I) -> Option<String>
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        candidates
+            .into_iter()
+            .map(|ssid| (ssid, self.score_candidate(ssid)))
+            .filter(|(_, score)| *score > i32::MIN)
+            .max_by_key(|(_, score)| *score)
+            .map(|(ssid, _)| ssid.to_owned())
+    }
+
+    /// Adds a saved network credential (replacing any existing entry for
+    /// the same SSID) and persists the store to NVS.
+    pub fn add_saved_network(
+        &self,
+        ssid: String,
+        password: Option<String>,
+        priority: u8,
+        auth: WifiAuthMethod,
+        identity: Option<String>,
+    ) -> Result<()> {
+        let mut guard = self.inner.lock().unwrap();
+        guard.saved_networks.retain(|network| network.ssid != ssid);
+        guard.saved_networks.push(SavedNetwork {
+            ssid,
+            password,
+            priority,
+            auth,
+            identity,
+        });
+        hardware::save_saved_networks(&guard.saved_networks)
+    }
+
+    /// Removes a saved network credential by SSID, if present, and
+    /// persists the store to NVS.
+    pub fn remove_saved_network(&self, ssid: &str) -> Result<()> {
+        let mut guard = self.inner.lock().unwrap();
+        guard.saved_networks.retain(|network| network.ssid != ssid);
+        hardware::save_saved_networks(&guard.saved_networks)
+    }
+
+    /// Lists saved networks with passwords redacted, for UI display.
+    pub fn list_saved_networks(&self) -> Vec<SavedNetworkSummary> {
+        self.inner
+            .lock()
+            .unwrap()
+            .saved_networks
+            .iter()
+            .map(|network| SavedNetworkSummary {
+                ssid: network.ssid.clone(),
+                has_password: network.password.is_some(),
+                priority: network.priority,
+            })
+            .collect()
     }
 
-    /// Fallback to AP mode, typically after repeated STA failures.
+    /// Returns the saved network with the lowest (most preferred)
+    /// `priority` that's currently visible in `scan_cache`, ties within that
+    /// priority tier broken by `select_best_candidate` (RSSI, penalized for
+    /// recent failures). `None` if no saved network is currently in range.
+    pub fn best_saved_candidate(&self) -> Option<SavedNetwork> {
+        let guard = self.inner.lock().unwrap();
+        let min_priority = guard
+            .saved_networks
+            .iter()
+            .filter(|network| guard.scan_cache.iter().any(|seen| seen.ssid == network.ssid))
+            .map(|network| network.priority)
+            .min()?;
+        let candidate_ssids: Vec<String> = guard
+            .saved_networks
+            .iter()
+            .filter(|network| network.priority == min_priority)
+            .map(|network| network.ssid.clone())
+            .collect();
+        drop(guard);
+
+        let best_ssid = self.select_best_candidate(candidate_ssids.iter().map(String::as_str))?;
+
+        self.inner
+            .lock()
+            .unwrap()
+            .saved_networks
+            .iter()
+            .find(|network| network.ssid == best_ssid)
+            .cloned()
+    }
+
+    /// Fallback to AP mode, typically after repeated STA failures. Starts
+    /// the captive-portal DNS responder so client devices' connectivity
+    /// checks resolve straight to this firmware and the provisioning UI
+    /// pops automatically.
     pub fn enable_captive_ap(&self, ssid: Option<String>) {
         let mut guard = self.inner.lock().unwrap();
         guard.snapshot.mode = WifiMode::AccessPoint;
@@ -181,6 +492,13 @@ impl WifiController {
             ap.ssid = custom_ssid;
         }
         guard.snapshot.access_point = Some(ap);
+        guard.sta_retry_count = 0;
+        guard.next_attempt_at = None;
+        drop(guard);
+
+        if let Err(err) = hardware::start_captive_dns() {
+            warn!("Failed to start captive-portal DNS responder: {err}");
+        }
     }
 
     /// Marks the provisioning state as failed and returns to AP mode.
@@ -190,6 +508,48 @@ impl WifiController {
         guard.snapshot.station.connected = false;
     }
 
+    /// Updates station state from a got-IP event: refreshes IP/RSSI and
+    /// marks the link up without needing a fresh `connect_sta` round trip.
+    pub fn note_sta_connected(&self, ip: String, rssi: Option<i32>) {
+        let mut guard = self.inner.lock().unwrap();
+        guard.snapshot.station.connected = true;
+        guard.snapshot.station.ip = Some(ip);
+        if rssi.is_some() {
+            guard.snapshot.station.rssi = rssi;
+        }
+        guard.snapshot.provisioning_state = ProvisioningState::Idle;
+    }
+
+    /// Reacts to a STA disconnect event. If we were previously connected,
+    /// this is unexpected (a deliberate reconnect already resets `station`
+    /// via `begin_sta_attempt`), so flip to `Error` rather than leave the
+    /// UI showing a stale "connected" snapshot.
+    pub fn note_sta_disconnected(&self) {
+        let mut guard = self.inner.lock().unwrap();
+        if !guard.snapshot.station.connected {
+            return;
+        }
+        guard.snapshot.station.connected = false;
+        guard.snapshot.provisioning_state = ProvisioningState::Error;
+    }
+
+    /// Increments the SoftAP client count as a station associates.
+    pub fn note_ap_client_joined(&self) {
+        let mut guard = self.inner.lock().unwrap();
+        if let Some(ap) = guard.snapshot.access_point.as_mut() {
+            ap.client_count += 1;
+        }
+    }
+
+    /// Decrements the SoftAP client count as a station leaves, saturating
+    /// at zero rather than underflowing on a missed join event.
+    pub fn note_ap_client_left(&self) {
+        let mut guard = self.inner.lock().unwrap();
+        if let Some(ap) = guard.snapshot.access_point.as_mut() {
+            ap.client_count = ap.client_count.saturating_sub(1);
+        }
+    }
+
     /// Updates the most recent scan results (currently stubbed with cached data).
     pub fn scan_networks(&self) -> WifiScanResponse {
         #[cfg(target_os = "espidf")]
@@ -213,13 +573,26 @@ pub static CONTROLLER: Lazy<WifiController> = Lazy::new(WifiController::new);
 
 #[cfg(target_os = "espidf")]
 mod hardware {
-    use super::{WifiNetwork, CONTROLLER};
+    use super::{
+        sta_reconnect_delay, SavedNetwork, WifiAuthMethod, WifiNetwork, CONTROLLER,
+        STA_RECONNECT_MAX_ATTEMPTS,
+    };
+    use aes_gcm::{
+        aead::{Aead, KeyInit},
+        Aes256Gcm, Key, Nonce,
+    };
     use anyhow::{anyhow, Context, Result};
     use embedded_svc::wifi::{AuthMethod, ClientConfiguration, Configuration};
+    use pbkdf2::pbkdf2_hmac;
+    use rand::{rngs::OsRng, RngCore};
+    use sha2::Sha256;
     use esp_idf_svc::{
-        eventloop::EspSystemEventLoop,
+        eventloop::{EspSubscription, EspSystemEventLoop, System},
         hal::prelude::Peripherals,
-        wifi::{BlockingWifi, EspWifi},
+        ipv4::IpEvent,
+        nvs::EspNvs,
+        sys::{esp_efuse_mac_get_default, ESP_ERR_NVS_NOT_FOUND},
+        wifi::{BlockingWifi, EspWifi, WifiEvent},
     };
     use heapless::String as HeaplessString;
     use once_cell::sync::OnceCell;
@@ -227,9 +600,22 @@ mod hardware {
 
     use crate::NVS_PARTITION;
 
+    /// NVS namespace the saved-network credential store lives under.
+    const SAVED_NETWORKS_NVS_NAMESPACE: &str = "wifi_saved";
+    /// Single NVS key holding the whole saved-network list as JSON; the
+    /// list is small (a handful of entries at most), so one blob is
+    /// simpler than per-field keys for a variable-length collection.
+    const SAVED_NETWORKS_NVS_KEY: &str = "networks";
+    /// Upper bound on the serialized saved-network list.
+    const SAVED_NETWORKS_MAX_LEN: usize = 2048;
+
     struct Driver {
         wifi: Mutex<BlockingWifi<EspWifi<'static>>>,
         _sysloop: EspSystemEventLoop,
+        // Kept alive only to hold the subscriptions open; dropping either
+        // one unsubscribes it.
+        _wifi_event_sub: EspSubscription<'static, System>,
+        _ip_event_sub: EspSubscription<'static, System>,
     }
 
     static DRIVER: OnceCell<Driver> = OnceCell::new();
@@ -242,23 +628,86 @@ mod hardware {
             let nvs = NVS_PARTITION.clone();
             let wifi = EspWifi::new(peripherals.modem, sysloop.clone(), Some(nvs))?;
             let blocking = BlockingWifi::wrap(wifi, sysloop.clone())?;
+            let wifi_event_sub = sysloop
+                .subscribe::<WifiEvent, _>(handle_wifi_event)
+                .context("Failed to subscribe to Wi-Fi events")?;
+            let ip_event_sub = sysloop
+                .subscribe::<IpEvent, _>(handle_ip_event)
+                .context("Failed to subscribe to IP events")?;
             Ok(Driver {
                 wifi: Mutex::new(blocking),
                 _sysloop: sysloop,
+                _wifi_event_sub: wifi_event_sub,
+                _ip_event_sub: ip_event_sub,
             })
         })?;
         Ok(())
     }
 
-    pub(super) fn schedule_sta_connect(ssid: String, password: Option<String>) -> Result<()> {
+    /// Keeps `WifiSnapshot` live between `connect_sta`'s one-time samples:
+    /// an unexpected STA disconnect flips `provisioning_state` to `Error`,
+    /// and SoftAP associations/disassociations update `client_count`.
+    fn handle_wifi_event(event: WifiEvent) {
+        match event {
+            WifiEvent::StaDisconnected => CONTROLLER.note_sta_disconnected(),
+            WifiEvent::ApStaConnected(_) => CONTROLLER.note_ap_client_joined(),
+            WifiEvent::ApStaDisconnected(_) => CONTROLLER.note_ap_client_left(),
+            _ => {}
+        }
+    }
+
+    /// Refreshes station IP/RSSI as soon as DHCP hands out an address,
+    /// rather than only at the end of `connect_sta`'s initial join.
+    fn handle_ip_event(event: IpEvent) {
+        let IpEvent::DhcpIpAssigned(assignment) = event else {
+            return;
+        };
+        let rssi = DRIVER.get().and_then(|driver| {
+            driver
+                .wifi
+                .lock()
+                .unwrap()
+                .wifi_mut()
+                .driver_mut()
+                .get_ap_info()
+                .ok()
+                .map(|info| info.rssi)
+        });
+        CONTROLLER.note_sta_connected(assignment.ip_settings.ip.to_string(), rssi);
+    }
+
+    pub(super) fn schedule_sta_connect(
+        ssid: String,
+        password: Option<String>,
+        auth: WifiAuthMethod,
+        identity: Option<String>,
+    ) -> Result<()> {
         initialize()?;
-        thread::spawn(move || {
-            if let Err(err) = connect_sta(ssid.clone(), password) {
+        thread::spawn(move || reconnect_loop(ssid, password, auth, identity));
+        Ok(())
+    }
+
+    /// Drives STA connection attempts with exponential backoff: on failure,
+    /// waits `sta_reconnect_delay(attempt)` and tries again, up to
+    /// `STA_RECONNECT_MAX_ATTEMPTS` consecutive failures, after which it
+    /// gives up and falls back to the captive AP.
+    fn reconnect_loop(ssid: String, password: Option<String>, auth: WifiAuthMethod, identity: Option<String>) {
+        loop {
+            if let Err(err) = connect_sta(ssid.clone(), password.clone(), auth, identity.clone()) {
                 error!("STA connection attempt failed for '{ssid}': {err}");
-                CONTROLLER.mark_error();
+            } else {
+                return;
             }
-        });
-        Ok(())
+            let attempt = CONTROLLER.record_sta_failure(&ssid);
+            if attempt >= STA_RECONNECT_MAX_ATTEMPTS {
+                error!("Giving up on '{ssid}' after {attempt} failed attempts; falling back to AP mode");
+                CONTROLLER.enable_captive_ap(None);
+                return;
+            }
+            let delay = sta_reconnect_delay(attempt);
+            CONTROLLER.schedule_next_attempt(delay);
+            thread::sleep(delay);
+        }
     }
 
     pub(super) fn scan_networks() -> Result<Vec<WifiNetwork>> {
@@ -275,16 +724,42 @@ mod hardware {
         let results = wifi.scan().context("Wi-Fi scan failed")?;
         let networks = results
             .into_iter()
+            .filter(|ap| !ap.ssid.is_empty())
             .map(|ap| WifiNetwork {
                 ssid: ap.ssid.as_str().to_owned(),
                 rssi: ap.rssi,
-                secure: ap.auth_method != AuthMethod::None,
+                auth: map_auth_method(ap.auth_method),
+                channel: ap.channel,
+                bssid: ap.bssid,
             })
             .collect();
-        Ok(networks)
+        Ok(super::dedupe_strongest_per_ssid(networks))
     }
 
-    fn connect_sta(ssid: String, password: Option<String>) -> Result<()> {
+    /// Maps the driver's auth method onto our serializable `WifiAuthMethod`.
+    /// `AuthMethod` has a couple of exotic variants (e.g. WAPI) this
+    /// firmware doesn't support joining; those are reported as the closest
+    /// personal/enterprise bucket rather than failing the whole scan.
+    fn map_auth_method(auth_method: AuthMethod) -> super::WifiAuthMethod {
+        use super::WifiAuthMethod as Wam;
+        match auth_method {
+            AuthMethod::None => Wam::Open,
+            AuthMethod::WEP => Wam::Wep,
+            AuthMethod::WPA => Wam::Wpa,
+            AuthMethod::WPA2Personal | AuthMethod::WPAWPA2Personal => Wam::Wpa2Personal,
+            AuthMethod::WPA3Personal => Wam::Wpa3Personal,
+            AuthMethod::WPA2WPA3Personal => Wam::Wpa2Wpa3Personal,
+            AuthMethod::WPA2Enterprise => Wam::Wpa2Enterprise,
+            _ => Wam::Wpa2Personal,
+        }
+    }
+
+    fn connect_sta(
+        ssid: String,
+        password: Option<String>,
+        auth: super::WifiAuthMethod,
+        identity: Option<String>,
+    ) -> Result<()> {
         initialize()?;
         let driver = DRIVER
             .get()
@@ -296,7 +771,7 @@ mod hardware {
             let _ = wifi.stop();
         }
 
-        let client_config = build_client_config(&ssid, password.as_deref())?;
+        let client_config = build_client_config(&ssid, password.as_deref(), auth, identity.as_deref())?;
         wifi.set_configuration(&Configuration::Client(client_config))?;
         wifi.start()?;
         wifi.connect()?;
@@ -326,7 +801,26 @@ mod hardware {
         Ok(())
     }
 
-    fn build_client_config(ssid: &str, password: Option<&str>) -> Result<ClientConfiguration> {
+    fn build_client_config(
+        ssid: &str,
+        password: Option<&str>,
+        auth: super::WifiAuthMethod,
+        identity: Option<&str>,
+    ) -> Result<ClientConfiguration> {
+        use super::WifiAuthMethod as Wam;
+
+        // Enterprise auth is plumbing only (see `WifiAuthMethod`'s doc
+        // comment): no EAP identity/credential exchange with the driver is
+        // implemented, so fail loudly here rather than silently falling
+        // back to a personal join that would never authenticate against
+        // the RADIUS server.
+        if matches!(auth, Wam::Wpa2Enterprise) {
+            return Err(anyhow!(
+                "WPA2-Enterprise join is not implemented (identity {:?} was supplied, but no EAP credential exchange exists to use it)",
+                identity.unwrap_or("<none>")
+            ));
+        }
+
         let ssid_value = to_heapless::<32>(ssid)?;
         let mut password_value: HeaplessString<64> = HeaplessString::new();
         if let Some(secret) = password {
@@ -335,10 +829,14 @@ mod hardware {
                 .map_err(|_| anyhow!("Wi-Fi password exceeds 64 characters"))?;
         }
 
-        let auth_method = if password_value.is_empty() {
-            AuthMethod::None
-        } else {
-            AuthMethod::WPA2Personal
+        let auth_method = match auth {
+            Wam::Open => AuthMethod::None,
+            Wam::Wep => AuthMethod::WEP,
+            Wam::Wpa => AuthMethod::WPA,
+            Wam::Wpa2Personal => AuthMethod::WPA2Personal,
+            Wam::Wpa2Wpa3Personal => AuthMethod::WPA2WPA3Personal,
+            Wam::Wpa3Personal => AuthMethod::WPA3Personal,
+            Wam::Wpa2Enterprise => unreachable!("handled above"),
         };
 
         Ok(ClientConfiguration {
@@ -357,30 +855,495 @@ mod hardware {
             .map_err(|_| anyhow!("Value exceeds {} characters", N))?;
         Ok(result)
     }
+
+    const WIFI_CRED_KEY_LEN: usize = 32;
+    const WIFI_CRED_NONCE_LEN: usize = 12;
+    /// Fixed, non-secret domain-separation salt for `derive_cred_key`. It's
+    /// fine for this to be public: on its own it derives nothing, and it's
+    /// only ever combined with the chip's burned-in MAC, never persisted.
+    const WIFI_CRED_KEY_SALT: &[u8] = b"erik-wifi-saved-network-cred-key-v1";
+    const WIFI_CRED_KEY_PBKDF2_ITERATIONS: u32 = 100_000;
+
+    /// On-disk shape of `SavedNetwork`: `password` is AES-256-GCM ciphertext
+    /// (hex-encoded `nonce || ciphertext`, same framing as `main.rs`'s
+    /// `encrypt_with_app_key`) instead of the plaintext secret. Saved
+    /// networks are encrypted under `derive_cred_key`'s key rather than
+    /// `main.rs`'s login-password-derived app key, since
+    /// `auto_join_saved_network` needs to decrypt them at boot, before
+    /// anyone has logged into the admin UI to unlock that key.
+    #[derive(Serialize, Deserialize)]
+    struct PersistedSavedNetwork {
+        ssid: String,
+        password: Option<String>,
+        priority: u8,
+        #[serde(default = "super::default_saved_network_auth")]
+        auth: WifiAuthMethod,
+        #[serde(default)]
+        identity: Option<String>,
+    }
+
+    /// Derives the saved-network credential key from this chip's burned-in
+    /// eFuse MAC address rather than minting and storing a random key: a
+    /// stored key living in the very NVS namespace it protects is trivially
+    /// recovered by the same flash-dump attacker it's meant to defend
+    /// against. The MAC isn't a secret either, but it isn't written down
+    /// next to the ciphertext it protects, so reading it back requires a
+    /// live chip (or a full eFuse dump, not just a flash/NVS dump) rather
+    /// than a `strings` pass over the saved-networks blob. Like any
+    /// device-local secret on hardware without a secure element, it doesn't
+    /// withstand someone who can extract everything from the chip itself.
+    fn derive_cred_key() -> Result<[u8; WIFI_CRED_KEY_LEN]> {
+        let mut mac = [0u8; 6];
+        let err = unsafe { esp_efuse_mac_get_default(mac.as_mut_ptr()) };
+        if err != 0 {
+            return Err(anyhow!("Failed to read device MAC from eFuse (code {err})"));
+        }
+        let mut key = [0u8; WIFI_CRED_KEY_LEN];
+        pbkdf2_hmac::<Sha256>(&mac, WIFI_CRED_KEY_SALT, WIFI_CRED_KEY_PBKDF2_ITERATIONS, &mut key);
+        Ok(key)
+    }
+
+    /// Encrypts `plaintext` under `key` with a fresh random nonce, returning
+    /// hex-encoded `nonce || ciphertext`.
+    fn encrypt_password(key: &[u8; WIFI_CRED_KEY_LEN], plaintext: &str) -> String {
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key));
+        let mut nonce_bytes = [0u8; WIFI_CRED_NONCE_LEN];
+        OsRng.fill_bytes(&mut nonce_bytes);
+        let ciphertext = cipher
+            .encrypt(Nonce::from_slice(&nonce_bytes), plaintext.as_bytes())
+            .expect("AES-256-GCM encryption cannot fail for a well-formed key and nonce");
+        hex_encode(&[nonce_bytes.as_slice(), ciphertext.as_slice()].concat())
+    }
+
+    /// Inverse of `encrypt_password`. `None` on a wrong key, malformed hex,
+    /// or corrupted/tampered ciphertext.
+    fn decrypt_password(key: &[u8; WIFI_CRED_KEY_LEN], hex: &str) -> Option<String> {
+        let blob = hex_decode(hex).ok()?;
+        if blob.len() < WIFI_CRED_NONCE_LEN {
+            return None;
+        }
+        let (nonce_bytes, ciphertext) = blob.split_at(WIFI_CRED_NONCE_LEN);
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key));
+        let plaintext = cipher
+            .decrypt(Nonce::from_slice(nonce_bytes), ciphertext)
+            .ok()?;
+        String::from_utf8(plaintext).ok()
+    }
+
+    fn hex_encode(bytes: &[u8]) -> String {
+        bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+    }
+
+    fn hex_decode(s: &str) -> Result<Vec<u8>> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| {
+                u8::from_str_radix(s.get(i..i + 2).unwrap_or_default(), 16)
+                    .map_err(|_| anyhow!("Invalid hex string"))
+            })
+            .collect()
+    }
+
+    /// Loads the saved-network list from NVS, if any has been persisted
+    /// yet, decrypting each password under the saved-network credential key.
+    pub(super) fn load_saved_networks() -> Result<Vec<SavedNetwork>> {
+        let partition = NVS_PARTITION.clone();
+        let mut nvs = EspNvs::new(partition, SAVED_NETWORKS_NVS_NAMESPACE, true)
+            .context("Failed to open saved-network NVS namespace")?;
+        let cred_key = derive_cred_key()?;
+        let mut buffer = [0u8; SAVED_NETWORKS_MAX_LEN];
+        let persisted: Vec<PersistedSavedNetwork> = match nvs.get_str(SAVED_NETWORKS_NVS_KEY, &mut buffer) {
+            Ok(Some(json)) => {
+                serde_json::from_str(json).context("Failed to parse saved-network list")?
+            }
+            Ok(None) => Vec::new(),
+            Err(err) if err.code() == ESP_ERR_NVS_NOT_FOUND => Vec::new(),
+            Err(err) => return Err(err.into()),
+        };
+        Ok(persisted
+            .into_iter()
+            .map(|entry| SavedNetwork {
+                ssid: entry.ssid,
+                password: entry
+                    .password
+                    .and_then(|ciphertext| decrypt_password(&cred_key, &ciphertext)),
+                priority: entry.priority,
+                auth: entry.auth,
+                identity: entry.identity,
+            })
+            .collect())
+    }
+
+    /// Persists the saved-network list to NVS, overwriting what's there,
+    /// encrypting each password under the saved-network credential key.
+    pub(super) fn save_saved_networks(networks: &[SavedNetwork]) -> Result<()> {
+        let partition = NVS_PARTITION.clone();
+        let mut nvs = EspNvs::new(partition, SAVED_NETWORKS_NVS_NAMESPACE, true)
+            .context("Failed to open saved-network NVS namespace")?;
+        let cred_key = derive_cred_key()?;
+        let persisted: Vec<PersistedSavedNetwork> = networks
+            .iter()
+            .map(|network| PersistedSavedNetwork {
+                ssid: network.ssid.clone(),
+                password: network
+                    .password
+                    .as_deref()
+                    .map(|password| encrypt_password(&cred_key, password)),
+                priority: network.priority,
+                auth: network.auth,
+                identity: network.identity.clone(),
+            })
+            .collect();
+        let json = serde_json::to_string(&persisted)
+            .context("Failed to serialize saved-network list")?;
+        nvs.set_str(SAVED_NETWORKS_NVS_KEY, &json)
+            .context("Failed to persist saved-network list")?;
+        Ok(())
+    }
+
+    static CAPTIVE_DNS_RUNNING: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+    /// Starts the captive-portal DNS responder on `CAPTIVE_PORTAL_GATEWAY`
+    /// if it isn't already running. Idempotent: calling this while it's
+    /// already up is a no-op.
+    pub(super) fn start_captive_dns() -> Result<()> {
+        use std::sync::atomic::Ordering;
+
+        if CAPTIVE_DNS_RUNNING.swap(true, Ordering::SeqCst) {
+            return Ok(());
+        }
+
+        let bound = std::net::UdpSocket::bind((CAPTIVE_PORTAL_GATEWAY, CAPTIVE_DNS_PORT))
+            .context("Failed to bind captive-portal DNS socket")
+            .and_then(|socket| {
+                socket
+                    .set_read_timeout(Some(std::time::Duration::from_millis(500)))
+                    .context("Failed to set captive-portal DNS socket timeout")?;
+                Ok(socket)
+            });
+        let socket = match bound {
+            Ok(socket) => socket,
+            Err(err) => {
+                CAPTIVE_DNS_RUNNING.store(false, Ordering::SeqCst);
+                return Err(err);
+            }
+        };
+        thread::spawn(move || run_captive_dns(socket));
+        Ok(())
+    }
+
+    /// Signals the captive-portal DNS responder's background thread to
+    /// exit; it notices within one read-timeout interval.
+    pub(super) fn stop_captive_dns() {
+        use std::sync::atomic::Ordering;
+        CAPTIVE_DNS_RUNNING.store(false, Ordering::SeqCst);
+    }
+
+    fn run_captive_dns(socket: std::net::UdpSocket) {
+        use std::sync::atomic::Ordering;
+
+        let mut buffer = [0u8; 512];
+        while CAPTIVE_DNS_RUNNING.load(Ordering::SeqCst) {
+            match socket.recv_from(&mut buffer) {
+                Ok((len, source)) => {
+                    if let Some(reply) = build_captive_dns_reply(&buffer[..len]) {
+                        if let Err(err) = socket.send_to(&reply, source) {
+                            warn!("Failed to send captive-portal DNS reply: {err}");
+                        }
+                    }
+                }
+                Err(err)
+                    if matches!(
+                        err.kind(),
+                        std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    continue;
+                }
+                Err(err) => {
+                    warn!("Captive-portal DNS socket error: {err}");
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Builds a DNS reply that answers every query with an A record pointing
+    /// at `CAPTIVE_PORTAL_GATEWAY`: parses just the 12-byte header and the
+    /// question section of `query`, echoes the transaction ID and question
+    /// back, sets QR/AA, and appends a single short-TTL answer. Returns
+    /// `None` if `query` is too short to even contain a full question.
+    fn build_captive_dns_reply(query: &[u8]) -> Option<Vec<u8>> {
+        if query.len() < 12 {
+            return None;
+        }
+
+        let mut pos = 12;
+        while pos < query.len() && query[pos] != 0 {
+            pos += 1 + query[pos] as usize;
+        }
+        if pos >= query.len() || pos + 1 + 4 > query.len() {
+            return None;
+        }
+        let question = &query[12..pos + 1 + 4]; // QNAME + QTYPE + QCLASS
+
+        let gateway: std::net::Ipv4Addr = CAPTIVE_PORTAL_GATEWAY.parse().ok()?;
+
+        let mut reply = Vec::with_capacity(question.len() + 28);
+        reply.extend_from_slice(&query[0..2]); // transaction ID
+        let recursion_desired = query[2] & 0x01;
+        reply.push(0x84 | recursion_desired); // QR=1, Opcode=0, AA=1, TC=0
+        reply.push(0x00); // RA=0, Z=0, RCODE=0
+        reply.extend_from_slice(&1u16.to_be_bytes()); // QDCOUNT
+        reply.extend_from_slice(&1u16.to_be_bytes()); // ANCOUNT
+        reply.extend_from_slice(&0u16.to_be_bytes()); // NSCOUNT
+        reply.extend_from_slice(&0u16.to_be_bytes()); // ARCOUNT
+        reply.extend_from_slice(question);
+        reply.extend_from_slice(&[0xc0, 0x0c]); // NAME: pointer to the question
+        reply.extend_from_slice(&1u16.to_be_bytes()); // TYPE A
+        reply.extend_from_slice(&1u16.to_be_bytes()); // CLASS IN
+        reply.extend_from_slice(&60u32.to_be_bytes()); // TTL
+        reply.extend_from_slice(&4u16.to_be_bytes()); // RDLENGTH
+        reply.extend_from_slice(&gateway.octets());
+        Some(reply)
+    }
 }
 
 #[cfg(not(target_os = "espidf"))]
 mod hardware {
-    use super::WifiNetwork;
+    use super::{SavedNetwork, WifiNetwork};
     use anyhow::{anyhow, Result};
 
     pub(super) fn initialize() -> Result<()> {
         Ok(())
     }
 
-    pub(super) fn schedule_sta_connect(_ssid: String, _password: Option<String>) -> Result<()> {
+    pub(super) fn schedule_sta_connect(
+        _ssid: String,
+        _password: Option<String>,
+        _auth: super::WifiAuthMethod,
+        _identity: Option<String>,
+    ) -> Result<()> {
         Ok(())
     }
 
     pub(super) fn scan_networks() -> Result<Vec<WifiNetwork>> {
         Err(anyhow!("Wi-Fi scanning not available on this target"))
     }
+
+    pub(super) fn start_captive_dns() -> Result<()> {
+        Ok(())
+    }
+
+    pub(super) fn stop_captive_dns() {}
+
+    pub(super) fn load_saved_networks() -> Result<Vec<SavedNetwork>> {
+        Ok(Vec::new())
+    }
+
+    pub(super) fn save_saved_networks(_networks: &[SavedNetwork]) -> Result<()> {
+        Ok(())
+    }
 }
 
 pub fn initialize() -> Result<()> {
     hardware::initialize()
 }
 
-pub fn schedule_sta_connect(ssid: String, password: Option<String>) -> Result<()> {
-    hardware::schedule_sta_connect(ssid, password)
+pub fn schedule_sta_connect(
+    ssid: String,
+    password: Option<String>,
+    auth: WifiAuthMethod,
+    identity: Option<String>,
+) -> Result<()> {
+    hardware::schedule_sta_connect(ssid, password, auth, identity)
+}
+
+/// Boot-time entry point: scans, then joins the highest-priority saved
+/// network currently in range instead of defaulting straight to AP mode.
+/// Falls back to the captive AP if no saved network is visible or the
+/// connect attempt can't even be scheduled.
+pub fn auto_join_saved_network() {
+    CONTROLLER.scan_networks();
+    match CONTROLLER.best_saved_candidate() {
+        Some(network) => {
+            CONTROLLER.begin_sta_attempt(&network.ssid);
+            if let Err(err) = schedule_sta_connect(
+                network.ssid.clone(),
+                network.password,
+                network.auth,
+                network.identity.clone(),
+            ) {
+                error!("Failed to schedule STA connect for saved network '{}': {err}", network.ssid);
+                CONTROLLER.enable_captive_ap(None);
+            }
+        }
+        None => CONTROLLER.enable_captive_ap(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sta_reconnect_delay_doubles_with_jitter() {
+        let first = sta_reconnect_delay(1);
+        assert!(first >= STA_RECONNECT_BASE_DELAY);
+        assert!(first <= STA_RECONNECT_BASE_DELAY + STA_RECONNECT_BASE_DELAY / 5);
+
+        let expected_base = STA_RECONNECT_BASE_DELAY * 4;
+        let third = sta_reconnect_delay(3);
+        assert!(third >= expected_base);
+        assert!(third <= expected_base + expected_base / 5);
+    }
+
+    #[test]
+    fn sta_reconnect_delay_caps_at_max() {
+        let delay = sta_reconnect_delay(10);
+        assert!(delay >= STA_RECONNECT_MAX_DELAY);
+        assert!(delay <= STA_RECONNECT_MAX_DELAY + STA_RECONNECT_MAX_DELAY / 5);
+    }
+
+    #[test]
+    fn score_candidate_uses_rssi_for_visible_networks() {
+        let controller = WifiController::new();
+        assert_eq!(controller.score_candidate("Backyard"), -55);
+        assert_eq!(controller.score_candidate("Guest"), -68);
+    }
+
+    #[test]
+    fn score_candidate_scores_invisible_network_lowest() {
+        let controller = WifiController::new();
+        assert_eq!(controller.score_candidate("NotInRange"), i32::MIN);
+    }
+
+    #[test]
+    fn score_candidate_penalizes_recent_failure() {
+        let controller = WifiController::new();
+        controller.record_sta_failure("Backyard");
+        assert_eq!(
+            controller.score_candidate("Backyard"),
+            -55 - STA_RECENT_FAILURE_PENALTY
+        );
+    }
+
+    #[test]
+    fn select_best_candidate_picks_strongest_visible() {
+        let controller = WifiController::new();
+        let best = controller.select_best_candidate(["Guest", "Backyard", "NotInRange"]);
+        assert_eq!(best.as_deref(), Some("Backyard"));
+    }
+
+    #[test]
+    fn select_best_candidate_skips_invisible_candidates() {
+        let controller = WifiController::new();
+        assert_eq!(controller.select_best_candidate(["NotInRange"]), None);
+    }
+
+    #[test]
+    fn best_saved_candidate_breaks_ties_by_score() {
+        let controller = WifiController::new();
+        controller
+            .add_saved_network("Guest".into(), None, 1, WifiAuthMethod::Open, None)
+            .expect("save should succeed");
+        controller
+            .add_saved_network(
+                "Backyard".into(),
+                Some("secret".into()),
+                1,
+                WifiAuthMethod::Wpa2Personal,
+                None,
+            )
+            .expect("save should succeed");
+
+        let best = controller
+            .best_saved_candidate()
+            .expect("a saved network is in range");
+        assert_eq!(best.ssid, "Backyard");
+    }
+
+    #[test]
+    fn best_saved_candidate_prefers_lower_priority_over_stronger_signal() {
+        let controller = WifiController::new();
+        controller
+            .add_saved_network("Guest".into(), None, 0, WifiAuthMethod::Open, None)
+            .expect("save should succeed");
+        controller
+            .add_saved_network(
+                "Backyard".into(),
+                Some("secret".into()),
+                1,
+                WifiAuthMethod::Wpa2Personal,
+                None,
+            )
+            .expect("save should succeed");
+
+        let best = controller
+            .best_saved_candidate()
+            .expect("a saved network is in range");
+        assert_eq!(best.ssid, "Guest");
+    }
+
+    #[test]
+    fn dedupe_strongest_per_ssid_keeps_the_stronger_bssid() {
+        let networks = vec![
+            WifiNetwork {
+                ssid: "Backyard".into(),
+                rssi: -80,
+                auth: WifiAuthMethod::Wpa2Personal,
+                channel: 6,
+                bssid: [0x02, 0x00, 0x00, 0x00, 0x00, 0x01],
+            },
+            WifiNetwork {
+                ssid: "Backyard".into(),
+                rssi: -40,
+                auth: WifiAuthMethod::Wpa2Personal,
+                channel: 11,
+                bssid: [0x02, 0x00, 0x00, 0x00, 0x00, 0x02],
+            },
+            WifiNetwork {
+                ssid: "Guest".into(),
+                rssi: -60,
+                auth: WifiAuthMethod::Open,
+                channel: 1,
+                bssid: [0x02, 0x00, 0x00, 0x00, 0x00, 0x03],
+            },
+        ];
+
+        let deduped = dedupe_strongest_per_ssid(networks);
+
+        assert_eq!(deduped.len(), 2);
+        let backyard = deduped
+            .iter()
+            .find(|network| network.ssid == "Backyard")
+            .expect("Backyard should still be present");
+        assert_eq!(backyard.rssi, -40);
+        assert_eq!(backyard.bssid, [0x02, 0x00, 0x00, 0x00, 0x00, 0x02]);
+    }
+
+    #[test]
+    fn dedupe_strongest_per_ssid_sorts_strongest_first() {
+        let networks = vec![
+            WifiNetwork {
+                ssid: "Weak".into(),
+                rssi: -80,
+                auth: WifiAuthMethod::Open,
+                channel: 1,
+                bssid: [0x02, 0x00, 0x00, 0x00, 0x00, 0x01],
+            },
+            WifiNetwork {
+                ssid: "Strong".into(),
+                rssi: -30,
+                auth: WifiAuthMethod::Open,
+                channel: 6,
+                bssid: [0x02, 0x00, 0x00, 0x00, 0x00, 0x02],
+            },
+        ];
+
+        let deduped = dedupe_strongest_per_ssid(networks);
+
+        assert_eq!(deduped[0].ssid, "Strong");
+        assert_eq!(deduped[1].ssid, "Weak");
+    }
 }
@@ -17,9 +17,17 @@
 //! Note: ESP-IDF HAL requires different types for each GPIO pin, so adding buses
 //! still requires some manual updates to the initialization and reading functions.
 
-mod secrets;
 mod webserver;
 
+/// Wi-Fi credentials generated by `build.rs` from an external
+/// `erik.secrets.{toml,json,yaml,yml}` file if one is found, otherwise from
+/// environment variables (falling back to `src/secrets.rs.example`'s
+/// defaults), so nothing with
+/// real credentials ever needs to live in the checked-out source tree.
+mod secrets {
+    include!(concat!(env!("OUT_DIR"), "/secrets.rs"));
+}
+
 use std::{
     collections::HashMap,
     sync::{Mutex, OnceLock},